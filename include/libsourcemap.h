@@ -3,6 +3,7 @@
 
 typedef void lsm_view_t;
 typedef void lsm_index_t;
+typedef void lsm_builder_t;
 typedef void lsm_proguard_mapping_t;
 
 typedef struct lsm_token_s {
@@ -15,19 +16,78 @@ typedef struct lsm_token_s {
     const char *src;
     unsigned int src_len;
     unsigned int src_id;
+    /* UINT_MAX if the token extends to the end of the line */
+    unsigned int dst_end_col;
+    int exact;
 } lsm_token_t;
 
+typedef struct lsm_index_section_s {
+    unsigned int offset_line;
+    unsigned int offset_col;
+    /* NULL if the section embeds its map */
+    const char *url;
+    unsigned int url_len;
+    /* UINT_MAX if the section references a map that is not loaded */
+    unsigned int token_count;
+} lsm_index_section_t;
+
+typedef struct lsm_frame_s {
+    unsigned int line;
+    unsigned int col;
+    /* the minified function name or NULL */
+    const char *function_name;
+} lsm_frame_t;
+
+typedef struct lsm_symbolicated_frame_s {
+    int found;
+    lsm_token_t token;
+    const char *function_name;
+    unsigned int function_name_len;
+    /* the context lines joined with \n or NULL.  The first
+       pre_context_count lines come before the line of the frame. */
+    char *context;
+    unsigned int context_len;
+    unsigned int pre_context_count;
+} lsm_symbolicated_frame_t;
+
+typedef struct lsm_source_references_s {
+    /* NULL if there is no reference */
+    char *source_map_url;
+    unsigned int source_map_url_len;
+    /* set if the url is a data url with the inlined source map */
+    int source_map_inline;
+    char *debug_id;
+    unsigned int debug_id_len;
+} lsm_source_references_t;
+
 typedef struct lsm_error_s {
     char *message;
     int failed;
     int code;
 } lsm_error_t;
 
+typedef int (*lsm_write_func_t)(const char *data, size_t len, void *userdata);
+/* returns 1 and sets data_out if the section url was resolved, 0 if it
+   was not found and anything else to abort.  The data has to stay valid
+   until the flattening call returns. */
+typedef int (*lsm_resolve_func_t)(const char *url, size_t url_len,
+                                  const char **data_out, size_t *len_out,
+                                  void *userdata);
+
 int lsm_init(lsm_error_t *err);
 
 lsm_view_t *lsm_view_from_json(char *bytes, unsigned int len, lsm_error_t *err);
 lsm_view_t *lsm_view_from_memdb(char *bytes, unsigned int len, lsm_error_t *err);
+lsm_view_t *lsm_view_from_memdb_validated(char *bytes, unsigned int len,
+                                          lsm_error_t *err);
+/* the borrowed variants do not copy the buffer.  It must stay valid and
+   unmodified until the view is freed. */
+lsm_view_t *lsm_view_from_memdb_borrowed(char *bytes, unsigned int len,
+                                         lsm_error_t *err);
+lsm_view_t *lsm_view_from_memdb_borrowed_validated(char *bytes, unsigned int len,
+                                                   lsm_error_t *err);
 lsm_view_t *lsm_view_from_memdb_file(char *path, lsm_error_t *err);
+lsm_view_t *lsm_view_from_memdb_file_validated(char *path, lsm_error_t *err);
 void lsm_view_free(lsm_view_t *view);
 
 unsigned int lsm_view_get_token_count(const lsm_view_t *view, lsm_error_t *err);
@@ -36,6 +96,23 @@ unsigned int lsm_view_get_token(const lsm_view_t *view, unsigned int idx,
 int lsm_view_lookup_token(const lsm_view_t *view, unsigned int line,
                           unsigned int col, lsm_token_t *tok_out,
                           lsm_error_t *err);
+/* bias is 0 for the greatest lower bound and 1 for the least upper bound.
+   A max_distance of UINT_MAX disables the distance check. */
+int lsm_view_lookup_token_with_options(const lsm_view_t *view,
+                                       unsigned int line,
+                                       unsigned int col,
+                                       int bias,
+                                       int same_line,
+                                       unsigned int max_distance,
+                                       lsm_token_t *tok_out,
+                                       lsm_error_t *err);
+/* the returned tokens are freed with lsm_token_array_free */
+lsm_token_t *lsm_view_lookup_original(const lsm_view_t *view,
+                                      unsigned int src_id,
+                                      unsigned int line,
+                                      unsigned int col,
+                                      unsigned int *len_out,
+                                      lsm_error_t *err);
 unsigned int lsm_view_get_original_function_name(const lsm_view_t *view,
                                                  unsigned int line,
                                                  unsigned int col,
@@ -45,33 +122,200 @@ unsigned int lsm_view_get_original_function_name(const lsm_view_t *view,
                                                  lsm_error_t *err);
 unsigned int lsm_view_get_source_count(const lsm_view_t *view,\
                                        lsm_error_t *err);
+/* returns the contents of a source.  Memdb views decompress the contents
+   into their source cache and this returns a copy of the cached contents;
+   if must_free is set the copy is freed with lsm_buffer_free. */
 char *lsm_view_get_source_contents(const lsm_view_t *view,
                                    unsigned int src_id,
                                    unsigned int *len_out,
                                    int *must_free,
                                    lsm_error_t *err);
+/* like lsm_view_get_source_contents but returns the cached contents
+   themselves without copying them.  If must_free is set the contents hold a
+   reference to the cache entry that must be released with
+   lsm_source_contents_free. */
+const char *lsm_view_get_shared_source_contents(const lsm_view_t *view,
+                                                unsigned int src_id,
+                                                unsigned int *len_out,
+                                                int *must_free,
+                                                lsm_error_t *err);
 const char *lsm_view_get_source_name(const lsm_view_t *view,
                                      unsigned int src_id,
                                      unsigned int *len_out,
                                      lsm_error_t *err);
+/* returns NULL if the map has no debug id */
+const char *lsm_view_get_debug_id(const lsm_view_t *view,
+                                  unsigned int *len_out,
+                                  lsm_error_t *err);
+/* the returned lines are NUL terminated and freed with lsm_buffer_free_len.
+   The context joins the lines with \n, the first pre_count_out lines come
+   before the requested line. */
+char *lsm_view_get_source_line(const lsm_view_t *view,
+                               unsigned int src_id,
+                               unsigned int line,
+                               unsigned int *len_out,
+                               lsm_error_t *err);
+char *lsm_view_get_source_context(const lsm_view_t *view,
+                                  unsigned int src_id,
+                                  unsigned int line,
+                                  unsigned int pre,
+                                  unsigned int post,
+                                  unsigned int *len_out,
+                                  unsigned int *pre_count_out,
+                                  lsm_error_t *err);
+/* returns one frame per input frame which is freed with
+   lsm_symbolicated_frames_free.  Strings other than the context are
+   borrowed from the view. */
+lsm_symbolicated_frame_t *lsm_view_symbolicate_frames(const lsm_view_t *view,
+                                                      const lsm_frame_t *frames,
+                                                      unsigned int count,
+                                                      const char *minified_source,
+                                                      unsigned int context_lines,
+                                                      lsm_error_t *err);
+void lsm_symbolicated_frames_free(lsm_symbolicated_frame_t *frames,
+                                  unsigned int count);
+void lsm_view_set_source_cache_size(const lsm_view_t *view,
+                                    unsigned int size);
 int lsm_view_has_source_contents(const lsm_view_t *view,
                                  unsigned int src_id,
                                  lsm_error_t *err);
+/* maps the minified locations of view through other which maps the
+   intermediate file to the original sources.  Returns a new view. */
+lsm_view_t *lsm_view_compose(const lsm_view_t *view, const lsm_view_t *other,
+                             lsm_error_t *err);
+/* the returned buffer is freed with lsm_buffer_free. */
 char *lsm_view_dump_memdb(const lsm_view_t *view,
                           unsigned int *len_out,
                           int with_source_contents,
                           int with_names,
                           lsm_error_t *err);
+/* codec selects the source contents compression: 0 = brotli, 1 = none,
+   2 = zstd, 3 = deflate.  codec_level is 0-11 for brotli, 1-22 for zstd
+   and 0-9 for deflate.  Other levels fail with error code 24.  The
+   returned buffer is freed with lsm_buffer_free_len. */
+char *lsm_view_dump_memdb_with_options(const lsm_view_t *view,
+                                       unsigned int *len_out,
+                                       int with_source_contents,
+                                       int with_names,
+                                       int with_reverse_index,
+                                       const char *minified_source,
+                                       int codec,
+                                       int codec_level,
+                                       lsm_error_t *err);
+/* the returned buffer is freed with lsm_buffer_free_len. */
+char *lsm_view_dump_json(const lsm_view_t *view,
+                         unsigned int *len_out,
+                         lsm_error_t *err);
+int lsm_view_dump_memdb_to_path(const lsm_view_t *view,
+                                const char *path,
+                                int with_source_contents,
+                                int with_names,
+                                int with_reverse_index,
+                                const char *minified_source,
+                                int codec,
+                                int codec_level,
+                                lsm_error_t *err);
+int lsm_view_dump_memdb_to_callback(const lsm_view_t *view,
+                                    lsm_write_func_t callback,
+                                    void *userdata,
+                                    int with_source_contents,
+                                    int with_names,
+                                    int with_reverse_index,
+                                    const char *minified_source,
+                                    int codec,
+                                    int codec_level,
+                                    lsm_error_t *err);
+
+/* returns 1 if any reference was found.  The strings are freed with
+   lsm_source_references_free. */
+int lsm_discover_references(const char *source, unsigned int len,
+                            const char *url,
+                            lsm_source_references_t *out,
+                            lsm_error_t *err);
+void lsm_source_references_free(lsm_source_references_t *refs);
 
 lsm_index_t *lsm_index_from_json(char *bytes, unsigned int len, lsm_error_t *err);
 void lsm_index_free(lsm_index_t *index);
 int lsm_index_can_flatten(const lsm_index_t *index, lsm_error_t *err);
 lsm_view_t *lsm_index_into_view(lsm_index_t *index, lsm_error_t *err);
+/* dumps the index as memdb without flattening it.  All sections need to
+   have their maps loaded.  The returned buffer is freed with
+   lsm_buffer_free_len. */
+char *lsm_index_dump_memdb(const lsm_index_t *index,
+                           unsigned int *len_out,
+                           int with_source_contents,
+                           int with_names,
+                           int with_reverse_index,
+                           const char *minified_source,
+                           int codec,
+                           int codec_level,
+                           lsm_error_t *err);
+int lsm_index_dump_memdb_to_path(const lsm_index_t *index,
+                                 const char *path,
+                                 int with_source_contents,
+                                 int with_names,
+                                 int with_reverse_index,
+                                 const char *minified_source,
+                                 int codec,
+                                 int codec_level,
+                                 lsm_error_t *err);
+unsigned int lsm_index_get_section_count(const lsm_index_t *index,
+                                        lsm_error_t *err);
+/* returns 0 if the section does not exist */
+int lsm_index_get_section(const lsm_index_t *index, unsigned int section_idx,
+                          lsm_index_section_t *section_out,
+                          lsm_error_t *err);
+/* the src_id of the token refers to the sources of the section's map */
+int lsm_index_lookup_token(const lsm_index_t *index, unsigned int line,
+                           unsigned int col, lsm_token_t *tok_out,
+                           lsm_error_t *err);
+lsm_view_t *lsm_index_flatten_with(lsm_index_t *index,
+                                   lsm_resolve_func_t callback,
+                                   void *userdata,
+                                   lsm_error_t *err);
+lsm_view_t *lsm_index_flatten_with_directory(lsm_index_t *index,
+                                             const char *path,
+                                             lsm_error_t *err);
 
 int lsm_view_or_index_from_json(char *bytes, unsigned int len,
                                 lsm_view_t **view_out,
                                 lsm_index_t **idx_out,
                                 lsm_error_t *err);
+/* loads a source map from a data: URL or the inline source map of a
+   minified file.  Returns 1 for a view and 2 for an index. */
+int lsm_view_or_index_from_data_url(const char *url, unsigned int len,
+                                    lsm_view_t **view_out,
+                                    lsm_index_t **idx_out,
+                                    lsm_error_t *err);
+int lsm_view_or_index_from_minified_source(const char *source,
+                                           unsigned int len,
+                                           lsm_view_t **view_out,
+                                           lsm_index_t **idx_out,
+                                           lsm_error_t *err);
+
+lsm_builder_t *lsm_builder_new(const char *file, lsm_error_t *err);
+void lsm_builder_free(lsm_builder_t *builder);
+int lsm_builder_set_debug_id(lsm_builder_t *builder, const char *debug_id,
+                             lsm_error_t *err);
+unsigned int lsm_builder_add_source(lsm_builder_t *builder,
+                                    const char *source, unsigned int len,
+                                    lsm_error_t *err);
+/* contents can be NULL to clear them */
+int lsm_builder_set_source_contents(lsm_builder_t *builder,
+                                    unsigned int src_id,
+                                    const char *contents, unsigned int len,
+                                    lsm_error_t *err);
+unsigned int lsm_builder_add_name(lsm_builder_t *builder,
+                                  const char *name, unsigned int len,
+                                  lsm_error_t *err);
+/* src_id and name_id are UINT_MAX for tokens without source or name */
+int lsm_builder_add_token(lsm_builder_t *builder,
+                          unsigned int dst_line, unsigned int dst_col,
+                          unsigned int src_line, unsigned int src_col,
+                          unsigned int src_id, unsigned int name_id,
+                          lsm_error_t *err);
+/* consumes the builder */
+lsm_view_t *lsm_builder_into_view(lsm_builder_t *builder, lsm_error_t *err);
 
 lsm_proguard_mapping_t *lsm_proguard_mapping_from_bytes(char *bytes, size_t len, lsm_error_t *err);
 lsm_proguard_mapping_t *lsm_proguard_mapping_from_path(char *filename, lsm_error_t *err);
@@ -81,5 +325,10 @@ char *lsm_proguard_mapping_convert_dotted_path(
     lsm_proguard_mapping_t *view, const char *path, int lineno, lsm_error_t *err);
 
 void lsm_buffer_free(char *buf);
+/* frees buffers that come with a len_out.  len is the length that was
+   reported for the buffer. */
+void lsm_buffer_free_len(char *buf, unsigned int len);
+void lsm_source_contents_free(char *buf, unsigned int len);
+void lsm_token_array_free(lsm_token_t *tokens, unsigned int len);
 
 #endif
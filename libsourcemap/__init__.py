@@ -1,14 +1,22 @@
-from .highlevel import from_json, View, Token, Index, ProguardView
+from .highlevel import from_json, from_data_url, from_minified_source, \
+    discover_references, View, Token, Index, Builder, ProguardView, \
+    SymbolicatedFrame, SourceReferences, IndexSection
 from .exceptions import SourceMapError, IndexedSourceMap, BadJson, \
-    CannotFlatten, UnsupportedMemDbVersion, BadIo, MemDbDumpError, \
-    TooManySources, TooManyNames, LocationOverflow, AlreadyMemDb
+    CannotFlatten, UnsupportedMemDbVersion, UnsupportedMemDbByteOrder, BadIo, \
+    BadMemDb, MemDbChecksumMismatch, BadDataUrl, UnsupportedMediaType, \
+    NoInlineSourceMap, UnknownId, MemDbDumpError, TooManySources, \
+    TooManyNames, LocationOverflow, AlreadyMemDb, BadCompressionLevel
 
 __all__ = [
     # General stuff
-    'View', 'Index', 'Token', 'ProguardView', 'from_json',
+    'View', 'Index', 'Builder', 'Token', 'ProguardView', 'SymbolicatedFrame',
+    'SourceReferences', 'IndexSection', 'from_json', 'from_data_url',
+    'from_minified_source', 'discover_references',
 
     # Exceptions
     'SourceMapError', 'IndexedSourceMap', 'BadJson', 'CannotFlatten',
-    'UnsupportedMemDbVersion', 'BadIo', 'MemDbDumpError',
-    'TooManySources', 'TooManyNames', 'LocationOverflow', 'AlreadyMemDb'
+    'UnsupportedMemDbVersion', 'UnsupportedMemDbByteOrder', 'BadIo',
+    'BadMemDb', 'MemDbChecksumMismatch', 'BadDataUrl', 'UnsupportedMediaType',
+    'NoInlineSourceMap', 'UnknownId', 'MemDbDumpError', 'TooManySources',
+    'TooManyNames', 'LocationOverflow', 'AlreadyMemDb', 'BadCompressionLevel'
 ]
@@ -32,10 +32,38 @@ class UnsupportedMemDbVersion(SourceMapError):
     """Raised if an unsupported memdb is loaded."""
 
 
+class UnsupportedMemDbByteOrder(UnsupportedMemDbVersion):
+    """Raised if a memdb was written with a different byte order."""
+
+
 class BadIo(SourceMapError):
     """Raised if an IO error happened."""
 
 
+class BadMemDb(SourceMapError):
+    """Raised if a memdb is truncated or corrupted."""
+
+
+class MemDbChecksumMismatch(BadMemDb):
+    """Raised if the checksum of a memdb does not match its contents."""
+
+
+class BadDataUrl(SourceMapError):
+    """Raised if a data URL with a source map cannot be decoded."""
+
+
+class UnsupportedMediaType(BadDataUrl):
+    """Raised if a data URL holds something other than a JSON source map."""
+
+
+class NoInlineSourceMap(SourceMapError):
+    """Raised if a minified file does not contain an inline source map."""
+
+
+class UnknownId(SourceMapError):
+    """Raised if a builder is given a source or name id it does not know."""
+
+
 class MemDbDumpError(SourceMapError):
     """Raised if creating a memdb is not possible."""
 
@@ -56,15 +84,27 @@ class AlreadyMemDb(MemDbDumpError):
     """Cannot create a memdb from a memdb."""
 
 
+class BadCompressionLevel(MemDbDumpError):
+    """The compression level is not supported by the codec."""
+
+
 special_errors = {
     2: IndexedSourceMap,
     3: BadJson,
     4: CannotFlatten,
     5: UnsupportedMemDbVersion,
     6: BadIo,
+    7: BadMemDb,
+    8: MemDbChecksumMismatch,
+    9: UnsupportedMemDbByteOrder,
+    10: BadDataUrl,
+    11: UnsupportedMediaType,
+    12: NoInlineSourceMap,
+    13: UnknownId,
 
     20: TooManySources,
     21: TooManyNames,
     22: LocationOverflow,
     23: AlreadyMemDb,
+    24: BadCompressionLevel,
 }
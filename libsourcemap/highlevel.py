@@ -3,7 +3,7 @@ import os
 from collections import namedtuple
 
 from ._sourcemapnative import ffi as _ffi
-from ._compat import to_bytes, xrange, NULL_BYTE
+from ._compat import to_bytes, text_type, xrange, NULL_BYTE
 from .exceptions import SourceMapError, IndexedSourceMap, special_errors
 
 
@@ -12,8 +12,27 @@ dtor_debug_callback = None
 _lib = _ffi.dlopen(os.path.join(os.path.dirname(__file__), '_libsourcemap.so'))
 
 
-Token = namedtuple('Token', ['dst_line', 'dst_col', 'src', 'src_line',
-                             'src_col', 'src_id', 'name'])
+class Token(namedtuple('Token', ['dst_line', 'dst_col', 'src', 'src_line',
+                                   'src_col', 'src_id', 'name'])):
+    """A token of a sourcemap.  `dst_end_col` is the minified column where
+    the mapped segment ends (`None` if it extends to the end of the line)
+    and `exact` is false if a lookup fell back to a preceding token.  Both
+    are not part of the comparison of tokens.
+    """
+    dst_end_col = None
+    exact = True
+
+
+SymbolicatedFrame = namedtuple('SymbolicatedFrame', [
+    'token', 'function_name', 'pre_context', 'context_line', 'post_context'])
+
+
+SourceReferences = namedtuple('SourceReferences', [
+    'source_map_url', 'source_map_inline', 'debug_id'])
+
+
+IndexSection = namedtuple('IndexSection', [
+    'offset_line', 'offset_col', 'url', 'token_count'])
 
 
 def silentdtor(orig):
@@ -47,8 +66,44 @@ def decode_rust_str(ptr, len):
         return _ffi.unpack(ptr, len).decode('utf-8', 'replace')
 
 
+def _encode_minified_source(minified_source):
+    if minified_source is None:
+        return _ffi.NULL
+    return to_bytes(minified_source)
+
+
+# maps compression names to the codec id, the default level and the
+# range of supported levels
+_compression_codecs = {
+    'brotli': (0, 4, 0, 11),
+    None: (1, 0, 0, 0),
+    'zstd': (2, 3, 1, 22),
+    'deflate': (3, 6, 0, 9),
+}
+
+
+_lookup_biases = {
+    'glb': 0,
+    'lub': 1,
+}
+
+
+def _encode_compression(compression, compression_level):
+    try:
+        codec, default_level, min_level, max_level = \
+            _compression_codecs[compression]
+    except KeyError:
+        raise ValueError('Unknown compression %r' % (compression,))
+    if compression is None or compression_level is None:
+        compression_level = default_level
+    if not min_level <= compression_level <= max_level:
+        raise ValueError('Unsupported compression level %r for %s' %
+                         (compression_level, compression))
+    return codec, compression_level
+
+
 def convert_token(tok):
-    return Token(
+    rv = Token(
         tok.dst_line,
         tok.dst_col,
         decode_rust_str(tok.src, tok.src_len),
@@ -57,24 +112,17 @@ def convert_token(tok):
         tok.src_id,
         decode_rust_str(tok.name, tok.name_len)
     )
+    if tok.dst_end_col != 0xffffffff:
+        rv.dst_end_col = tok.dst_end_col
+    rv.exact = bool(tok.exact)
+    return rv
 
 
-def from_json(buffer, auto_flatten=True, raise_for_index=True):
-    """Parses a JSON string into either a view or an index.  If auto flatten
-    is enabled a sourcemap index that does not contain external references is
-    automatically flattened into a view.  By default if an index would be
-    returned an `IndexedSourceMap` error is raised instead which holds the
-    index.
-    """
-    buffer = to_bytes(buffer)
-
+def _view_or_index(func, buffer, auto_flatten, raise_for_index):
     view_out = _ffi.new('lsm_view_t **')
     index_out = _ffi.new('lsm_index_t **')
 
-    buffer = to_bytes(buffer)
-    rv = rustcall(
-        _lib.lsm_view_or_index_from_json,
-        buffer, len(buffer), view_out, index_out)
+    rv = rustcall(func, buffer, len(buffer), view_out, index_out)
     if rv == 1:
         return View._from_ptr(view_out[0])
     elif rv == 2:
@@ -89,6 +137,57 @@ def from_json(buffer, auto_flatten=True, raise_for_index=True):
         raise AssertionError('Unknown response from C ABI (%r)' % rv)
 
 
+def from_json(buffer, auto_flatten=True, raise_for_index=True):
+    """Parses a JSON string into either a view or an index.  If auto flatten
+    is enabled a sourcemap index that does not contain external references is
+    automatically flattened into a view.  By default if an index would be
+    returned an `IndexedSourceMap` error is raised instead which holds the
+    index.
+    """
+    return _view_or_index(_lib.lsm_view_or_index_from_json, to_bytes(buffer),
+                          auto_flatten, raise_for_index)
+
+
+def from_data_url(url, auto_flatten=True, raise_for_index=True):
+    """Loads a source map from a ``data:`` URL with either base64 or
+    percent-encoded JSON.  Other media types raise `UnsupportedMediaType`.
+    Indexes are handled like in :func:`from_json`.
+    """
+    return _view_or_index(_lib.lsm_view_or_index_from_data_url, to_bytes(url),
+                          auto_flatten, raise_for_index)
+
+
+def from_minified_source(source, auto_flatten=True, raise_for_index=True):
+    """Loads the source map that is inlined into a minified file as a
+    ``data:`` URL in its ``sourceMappingURL`` comment.  If there is none
+    `NoInlineSourceMap` is raised.  Indexes are handled like in
+    :func:`from_json`.
+    """
+    return _view_or_index(_lib.lsm_view_or_index_from_minified_source,
+                          to_bytes(source), auto_flatten, raise_for_index)
+
+
+def discover_references(source, url=None):
+    """Scans a minified JavaScript or CSS file for the ``sourceMappingURL``
+    and ``debugId`` comments.  Relative source map URLs are resolved
+    against the `url` of the minified file if it is given.  Returns a
+    `SourceReferences` tuple where `source_map_inline` indicates that the
+    URL is a ``data:`` URL with the inlined source map.
+    """
+    source = to_bytes(source)
+    refs = _ffi.new('lsm_source_references_t *')
+    rustcall(_lib.lsm_discover_references, source, len(source),
+             _ffi.NULL if url is None else to_bytes(url), refs)
+    try:
+        return SourceReferences(
+            decode_rust_str(refs.source_map_url, refs.source_map_url_len),
+            bool(refs.source_map_inline),
+            decode_rust_str(refs.debug_id, refs.debug_id_len),
+        )
+    finally:
+        _lib.lsm_source_references_free(refs)
+
+
 class View(object):
     """Provides a view of a sourcemap.  This can come from two sources:
 
@@ -111,23 +210,56 @@ class View(object):
             buffer, len(buffer)))
 
     @staticmethod
-    def from_memdb(buffer):
-        """Creates a sourcemap view from MemDB bytes."""
-        buffer = to_bytes(buffer)
-        return View._from_ptr(rustcall(
-            _lib.lsm_view_from_memdb,
-            buffer, len(buffer)))
+    def from_memdb(buffer, validate=False, copy=True):
+        """Creates a sourcemap view from MemDB bytes.  If `validate` is
+        enabled the structure of the MemDB is verified upfront and a
+        `BadMemDb` error is raised for broken data.  If the checksums do
+        not match the more specific `MemDbChecksumMismatch` is raised.
+
+        If `copy` is disabled the view uses the memory of the buffer
+        directly.  In that case the buffer can be any object supporting
+        the buffer protocol (like a `bytearray` or `mmap`).  It is kept
+        alive as long as the view but it must not be modified or closed.
+        """
+        if copy:
+            buffer = to_bytes(buffer)
+            if validate:
+                func = _lib.lsm_view_from_memdb_validated
+            else:
+                func = _lib.lsm_view_from_memdb
+            return View._from_ptr(rustcall(func, buffer, len(buffer)))
+
+        if isinstance(buffer, text_type):
+            buffer = to_bytes(buffer)
+        data = _ffi.from_buffer(buffer)
+        if validate:
+            func = _lib.lsm_view_from_memdb_borrowed_validated
+        else:
+            func = _lib.lsm_view_from_memdb_borrowed
+        try:
+            rv = View._from_ptr(rustcall(func, data, len(data)))
+        except Exception:
+            _ffi.release(data)
+            raise
+        # the cdata keeps the buffer alive as long as the view
+        rv._buffer = data
+        return rv
 
     @staticmethod
-    def from_memdb_file(path):
+    def from_memdb_file(path, validate=False):
         """Creates a sourcemap view from MemDB at a given file."""
         path = to_bytes(path)
-        return View._from_ptr(rustcall(_lib.lsm_view_from_memdb_file, path))
+        if validate:
+            func = _lib.lsm_view_from_memdb_file_validated
+        else:
+            func = _lib.lsm_view_from_memdb_file
+        return View._from_ptr(rustcall(func, path))
 
     @staticmethod
     def _from_ptr(ptr):
         rv = object.__new__(View)
         rv._ptr = ptr
+        rv._buffer = None
         return rv
 
     def _get_ptr(self):
@@ -135,31 +267,142 @@ class View(object):
             raise RuntimeError('View is closed')
         return self._ptr
 
-    def dump_memdb(self, with_source_contents=True, with_names=True):
-        """Dumps a sourcemap in MemDB format into bytes."""
+    def compose(self, other):
+        """Composes this view with the view of the intermediate file it
+        maps to.  The returned view maps the minified locations of this
+        view straight to the original sources of `other` and carries its
+        names and source contents.  Tokens that cannot be mapped through
+        `other` are kept as unmapped tokens so that they end the previous
+        mapping.
+        """
+        return View._from_ptr(rustcall(
+            _lib.lsm_view_compose, self._get_ptr(), other._get_ptr()))
+
+    def dump_memdb(self, with_source_contents=True, with_names=True,
+                   minified_source=None,
+                   compression='brotli', compression_level=None,
+                   with_reverse_index=True):
+        """Dumps a sourcemap in MemDB format into bytes.  If the minified
+        source is provided the information needed to resolve original
+        function names is embedded as well.  MemDB views can be dumped
+        again to strip data or to upgrade them to the current format.
+
+        Source contents are compressed with `compression` which can be
+        ``'brotli'``, ``'zstd'``, ``'deflate'`` or `None` to store them
+        uncompressed.  `compression_level` overrides the codec's default
+        level (0 to 11 for brotli, 1 to 22 for zstd and 0 to 9 for
+        deflate).  The reverse index speeds up `lookup_original` and can be
+        left out with `with_reverse_index` to save space.
+        """
         len_out = _ffi.new('unsigned int *')
         buf = rustcall(
-            _lib.lsm_view_dump_memdb,
+            _lib.lsm_view_dump_memdb_with_options,
             self._get_ptr(), len_out,
-            with_source_contents, with_names)
+            with_source_contents, with_names, with_reverse_index,
+            _encode_minified_source(minified_source),
+            *_encode_compression(compression, compression_level))
         try:
             rv = _ffi.unpack(buf, len_out[0])
         finally:
-            _lib.lsm_buffer_free(buf)
+            _lib.lsm_buffer_free_len(buf, len_out[0])
         return rv
 
-    def lookup_token(self, line, col):
+    def dump_json(self):
+        """Dumps the sourcemap as JSON into bytes.  For MemDB views the
+        sourcemap is reconstructed from the MemDB.
+        """
+        len_out = _ffi.new('unsigned int *')
+        buf = rustcall(_lib.lsm_view_dump_json, self._get_ptr(), len_out)
+        try:
+            rv = _ffi.unpack(buf, len_out[0])
+        finally:
+            _lib.lsm_buffer_free_len(buf, len_out[0])
+        return rv
+
+    def dump_memdb_to_path(self, path, with_source_contents=True,
+                           with_names=True, minified_source=None,
+                           compression='brotli', compression_level=None,
+                           with_reverse_index=True):
+        """Like `dump_memdb` but writes the MemDB into a file at the
+        given path.
+        """
+        rustcall(_lib.lsm_view_dump_memdb_to_path,
+                 self._get_ptr(), to_bytes(path),
+                 with_source_contents, with_names, with_reverse_index,
+                 _encode_minified_source(minified_source),
+                 *_encode_compression(compression, compression_level))
+
+    def dump_memdb_to_stream(self, f, with_source_contents=True,
+                             with_names=True, minified_source=None,
+                             compression='brotli', compression_level=None,
+                             with_reverse_index=True):
+        """Like `dump_memdb` but streams the MemDB into a file-like object.
+        The object only needs to support `write` so this can be used with
+        pipes or compression streams.
+        """
+        errors = []
+
+        @_ffi.callback('lsm_write_func_t')
+        def write(data, len, userdata):
+            try:
+                f.write(_ffi.unpack(data, len))
+            except Exception as e:
+                errors.append(e)
+                return 1
+            return 0
+
+        try:
+            rustcall(_lib.lsm_view_dump_memdb_to_callback,
+                     self._get_ptr(), write, _ffi.NULL,
+                     with_source_contents, with_names, with_reverse_index,
+                     _encode_minified_source(minified_source),
+                     *_encode_compression(compression, compression_level))
+        except SourceMapError:
+            if errors:
+                raise errors[0]
+            raise
+
+    def lookup_token(self, line, col, bias='glb', same_line=False,
+                     max_distance=None):
         """Given a minified location, this tries to locate the closest
         token that is a match.  Returns `None` if no match can be found.
+
+        By default this finds the closest token at or before the location
+        (``'glb'``) which might be on a previous line.  With a bias of
+        ``'lub'`` the closest token at or after the location is found
+        instead.  `same_line` only matches tokens on the same line and
+        `max_distance` limits the distance in columns.
         """
         # Silently ignore underflows
         if line < 0 or col < 0:
             return None
+        try:
+            bias = _lookup_biases[bias]
+        except KeyError:
+            raise ValueError('Unknown bias %r' % (bias,))
+        if max_distance is None:
+            max_distance = 0xffffffff
         tok_out = _ffi.new('lsm_token_t *')
-        if rustcall(_lib.lsm_view_lookup_token, self._get_ptr(),
-                    line, col, tok_out):
+        if rustcall(_lib.lsm_view_lookup_token_with_options, self._get_ptr(),
+                    line, col, bias, same_line, max_distance, tok_out):
             return convert_token(tok_out[0])
 
+    def lookup_original(self, src_id, line, col=0):
+        """Given an original location this returns the list of tokens
+        that map to it, ordered by their minified location.  If there is
+        no token at the column the closest column before it is used or if
+        there is none the first column after it on the same line.
+        """
+        if line < 0 or col < 0:
+            return []
+        len_out = _ffi.new('unsigned int *')
+        tokens = rustcall(_lib.lsm_view_lookup_original, self._get_ptr(),
+                          src_id, line, col, len_out)
+        try:
+            return [convert_token(tokens[idx]) for idx in range(len_out[0])]
+        finally:
+            _lib.lsm_token_array_free(tokens, len_out[0])
+
     def get_original_function_name(self, line, col, minified_name,
                                    minified_source):
         """Given a token location and a minified function name and the
@@ -184,21 +427,125 @@ class View(object):
             # library)
             pass
 
+    def symbolicate_frames(self, frames, minified_source=None,
+                           context_lines=5):
+        """Symbolicates a list of minified frames in one go.  Each frame
+        is a ``(line, col, function_name)`` tuple where the function name
+        is the minified name or `None`.  JSON views need the minified
+        source to resolve original function names.
+
+        Returns a list with a `SymbolicatedFrame` or `None` for every
+        frame.  The context is `None` if the source is not embedded.
+        """
+        frames = list(frames)
+        c_frames = _ffi.new('lsm_frame_t[]', len(frames))
+        keepalive = []
+        for c_frame, (line, col, function_name) in zip(c_frames, frames):
+            # negative locations cannot be found
+            if line < 0 or col < 0:
+                line = col = 0xffffffff
+            c_frame.line = line
+            c_frame.col = col
+            if function_name is not None:
+                function_name = _ffi.new('char[]', to_bytes(function_name))
+                keepalive.append(function_name)
+                c_frame.function_name = function_name
+
+        rv = rustcall(_lib.lsm_view_symbolicate_frames, self._get_ptr(),
+                      c_frames, len(frames),
+                      _encode_minified_source(minified_source),
+                      max(context_lines, 0))
+        try:
+            result = []
+            for idx, (line, col, _) in enumerate(frames):
+                frame = rv[idx]
+                if not frame.found or line < 0 or col < 0:
+                    result.append(None)
+                    continue
+                pre_context = context_line = post_context = None
+                if frame.context:
+                    lines = _ffi.unpack(frame.context, frame.context_len) \
+                        .decode('utf-8').split(u'\n')
+                    pre_count = frame.pre_context_count
+                    pre_context = lines[:pre_count]
+                    context_line = lines[pre_count]
+                    post_context = lines[pre_count + 1:]
+                result.append(SymbolicatedFrame(
+                    convert_token(frame.token),
+                    decode_rust_str(frame.function_name,
+                                    frame.function_name_len),
+                    pre_context,
+                    context_line,
+                    post_context,
+                ))
+            return result
+        finally:
+            _lib.lsm_symbolicated_frames_free(rv, len(frames))
+
     def get_source_contents(self, src_id):
         """Given a source ID this returns the embedded sourcecode if there
         is.  The sourcecode is returned as UTF-8 bytes for more efficient
         processing.
+
+        For memdb views the contents are read from the source cache
+        without an intermediate copy (`lsm_view_get_shared_source_contents`)
+        so repeated calls only decompress the source once.
         """
         len_out = _ffi.new('unsigned int *')
         must_free = _ffi.new('int *')
-        rv = rustcall(_lib.lsm_view_get_source_contents,
+        rv = rustcall(_lib.lsm_view_get_shared_source_contents,
                       self._get_ptr(), src_id, len_out, must_free)
         if rv:
             try:
                 return _ffi.unpack(rv, len_out[0])
             finally:
                 if must_free[0]:
-                    _lib.lsm_buffer_free(rv)
+                    _lib.lsm_source_contents_free(rv, len_out[0])
+
+    def get_source_line(self, src_id, line):
+        """Returns a line of the embedded sourcecode without the line
+        terminator or `None` if the line does not exist.
+        """
+        if line < 0:
+            return None
+        len_out = _ffi.new('unsigned int *')
+        rv = rustcall(_lib.lsm_view_get_source_line,
+                      self._get_ptr(), src_id, line, len_out)
+        if rv:
+            try:
+                return _ffi.unpack(rv, len_out[0]).decode('utf-8')
+            finally:
+                _lib.lsm_buffer_free_len(rv, len_out[0])
+
+    def get_source_context(self, src_id, line, pre=5, post=5):
+        """Returns a line of the embedded sourcecode with up to `pre`
+        lines before and `post` lines after it.  The return value is a
+        tuple in the form ``(pre_lines, line, post_lines)`` or `None` if
+        the line does not exist.
+        """
+        if line < 0:
+            return None
+        len_out = _ffi.new('unsigned int *')
+        pre_count_out = _ffi.new('unsigned int *')
+        rv = rustcall(_lib.lsm_view_get_source_context,
+                      self._get_ptr(), src_id, line, max(pre, 0),
+                      max(post, 0), len_out, pre_count_out)
+        if not rv:
+            return None
+        try:
+            lines = _ffi.unpack(rv, len_out[0]).decode('utf-8').split(u'\n')
+        finally:
+            _lib.lsm_buffer_free_len(rv, len_out[0])
+        pre_count = pre_count_out[0]
+        return lines[:pre_count], lines[pre_count], lines[pre_count + 1:]
+
+    def set_source_cache_size(self, size):
+        """Sets the maximum size in bytes of the cache of decompressed
+        sources.  The cache only exists for MemDB views and avoids
+        decompressing sources over and over again.  A size of zero
+        disables the cache.
+        """
+        _lib.lsm_view_set_source_cache_size(self._get_ptr(), size)
 
     def has_source_contents(self, src_id):
         """Checks if some sources exist."""
@@ -213,6 +560,15 @@ class View(object):
         if rv:
             return decode_rust_str(rv, len_out[0])
 
+    def get_debug_id(self):
+        """Returns the debug id of the map or `None`.  For memdb views
+        this is the debug id the memdb was dumped with.
+        """
+        len_out = _ffi.new('unsigned int *')
+        rv = rustcall(_lib.lsm_view_get_debug_id, self._get_ptr(), len_out)
+        if rv:
+            return decode_rust_str(rv, len_out[0])
+
     def get_source_count(self):
         """Returns the number of sources."""
         return rustcall(_lib.lsm_view_get_source_count,
@@ -242,6 +598,10 @@ class View(object):
         if self._ptr:
             _lib.lsm_view_free(self._ptr)
         self._ptr = None
+        # release borrowed memory explicitly once the view is gone
+        if self._buffer is not None:
+            _ffi.release(self._buffer)
+        self._buffer = None
 
 
 class Index(object):
@@ -268,13 +628,85 @@ class Index(object):
             raise RuntimeError('Index is closed')
         return self._ptr
 
+    def dump_memdb(self, with_source_contents=True, with_names=True,
+                   minified_source=None,
+                   compression='brotli', compression_level=None,
+                   with_reverse_index=True):
+        """Dumps the index in MemDB format into bytes without flattening
+        it into a view first.  The sources and names of the sections are
+        merged.  All sections need to have their maps embedded and must
+        not overlap.  The arguments are the same as for `View.dump_memdb`.
+        """
+        len_out = _ffi.new('unsigned int *')
+        buf = rustcall(
+            _lib.lsm_index_dump_memdb,
+            self._get_ptr(), len_out,
+            with_source_contents, with_names, with_reverse_index,
+            _encode_minified_source(minified_source),
+            *_encode_compression(compression, compression_level))
+        try:
+            rv = _ffi.unpack(buf, len_out[0])
+        finally:
+            _lib.lsm_buffer_free_len(buf, len_out[0])
+        return rv
+
+    def dump_memdb_to_path(self, path, with_source_contents=True,
+                           with_names=True, minified_source=None,
+                           compression='brotli', compression_level=None,
+                           with_reverse_index=True):
+        """Like `dump_memdb` but writes the MemDB into a file at the
+        given path.
+        """
+        rustcall(_lib.lsm_index_dump_memdb_to_path,
+                 self._get_ptr(), to_bytes(path),
+                 with_source_contents, with_names, with_reverse_index,
+                 _encode_minified_source(minified_source),
+                 *_encode_compression(compression, compression_level))
+
+    def get_sections(self):
+        """Returns the sections of the index as `IndexSection` tuples.
+        `token_count` is `None` for sections with a map that is not
+        loaded.
+        """
+        section_out = _ffi.new('lsm_index_section_t *')
+        rv = []
+        count = rustcall(_lib.lsm_index_get_section_count, self._get_ptr())
+        for idx in xrange(count):
+            if not rustcall(_lib.lsm_index_get_section, self._get_ptr(),
+                            idx, section_out):
+                break
+            section = section_out[0]
+            rv.append(IndexSection(
+                section.offset_line,
+                section.offset_col,
+                decode_rust_str(section.url, section.url_len),
+                section.token_count
+                if section.token_count != 0xffffffff else None,
+            ))
+        return rv
+
+    def lookup_token(self, line, col):
+        """Looks up the closest token at or before the minified location
+        without flattening the index.  The `src_id` of the token refers
+        to the sources of the section's map.  Returns `None` if there is
+        no match, the section's map is not loaded or the sections overlap.
+        """
+        if line < 0 or col < 0:
+            return None
+        tok_out = _ffi.new('lsm_token_t *')
+        if rustcall(_lib.lsm_index_lookup_token, self._get_ptr(),
+                    line, col, tok_out):
+            return convert_token(tok_out[0])
+
     @property
     def can_flatten(self):
         """True if the index does not contain external references."""
         return rustcall(_lib.lsm_index_can_flatten, self._get_ptr()) == 1
 
     def into_view(self):
-        """Converts the index into a view"""
+        """Converts the index into a view.  Indexes with overlapping
+        sections cannot be flattened.
+        """
         try:
             return View._from_ptr(rustcall(
                 _lib.lsm_index_into_view,
@@ -282,6 +714,53 @@ class Index(object):
         finally:
             self._ptr = None
 
+    def flatten_with(self, resolver):
+        """Converts the index into a view and loads sections that refer
+        to an external map through `resolver`.  It is called with the URL
+        of each such section and returns the bytes of the map or `None`
+        if it cannot be found, in which case `CannotFlatten` is raised.
+        """
+        errors = []
+        # keeps the returned data alive until flattening finishes
+        buffers = []
+
+        @_ffi.callback('lsm_resolve_func_t')
+        def resolve(url, url_len, data_out, len_out, userdata):
+            try:
+                data = resolver(decode_rust_str(url, url_len))
+            except Exception as e:
+                errors.append(e)
+                return -1
+            if data is None:
+                return 0
+            buf = _ffi.from_buffer(to_bytes(data))
+            buffers.append(buf)
+            data_out[0] = buf
+            len_out[0] = len(buf)
+            return 1
+
+        try:
+            return View._from_ptr(rustcall(
+                _lib.lsm_index_flatten_with,
+                self._get_ptr(), resolve, _ffi.NULL))
+        except SourceMapError:
+            if errors:
+                raise errors[0]
+            raise
+        finally:
+            self._ptr = None
+
+    def flatten_with_directory(self, path):
+        """Like `flatten_with` but loads external section maps from files
+        relative to the directory at `path`.
+        """
+        try:
+            return View._from_ptr(rustcall(
+                _lib.lsm_index_flatten_with_directory,
+                self._get_ptr(), to_bytes(path)))
+        finally:
+            self._ptr = None
+
     @silentdtor
     def __del__(self):
         if self._ptr:
@@ -289,6 +768,75 @@ class Index(object):
         self._ptr = None
 
 
+class Builder(object):
+    """Builds a sourcemap.  Sources and names are registered first and
+    tokens refer to them by id.  `into_view` turns the builder into a
+    `View` which can then be dumped as JSON or MemDB.
+    """
+
+    def __init__(self, file=None, debug_id=None):
+        self._ptr = rustcall(
+            _lib.lsm_builder_new,
+            _ffi.NULL if file is None else to_bytes(file))
+        if debug_id is not None:
+            rustcall(_lib.lsm_builder_set_debug_id, self._ptr,
+                     to_bytes(debug_id))
+
+    def _get_ptr(self):
+        if not self._ptr:
+            raise RuntimeError('Builder is closed')
+        return self._ptr
+
+    def add_source(self, source):
+        """Registers a source and returns its id."""
+        source = to_bytes(source)
+        return rustcall(_lib.lsm_builder_add_source, self._get_ptr(),
+                        source, len(source))
+
+    def set_source_contents(self, src_id, contents):
+        """Sets the contents of a source or clears them if `contents`
+        is `None`.
+        """
+        if contents is None:
+            rustcall(_lib.lsm_builder_set_source_contents, self._get_ptr(),
+                     src_id, _ffi.NULL, 0)
+        else:
+            contents = to_bytes(contents)
+            rustcall(_lib.lsm_builder_set_source_contents, self._get_ptr(),
+                     src_id, contents, len(contents))
+
+    def add_name(self, name):
+        """Registers a name and returns its id."""
+        name = to_bytes(name)
+        return rustcall(_lib.lsm_builder_add_name, self._get_ptr(),
+                        name, len(name))
+
+    def add_token(self, dst_line, dst_col, src_line=0, src_col=0,
+                  src_id=None, name_id=None):
+        """Adds a token.  Tokens without a source only end the previous
+        token.
+        """
+        rustcall(_lib.lsm_builder_add_token, self._get_ptr(),
+                 dst_line, dst_col, src_line, src_col,
+                 0xffffffff if src_id is None else src_id,
+                 0xffffffff if name_id is None else name_id)
+
+    def into_view(self):
+        """Converts the builder into a view."""
+        try:
+            return View._from_ptr(rustcall(
+                _lib.lsm_builder_into_view,
+                self._get_ptr()))
+        finally:
+            self._ptr = None
+
+    @silentdtor
+    def __del__(self):
+        if self._ptr:
+            _lib.lsm_builder_free(self._ptr)
+        self._ptr = None
+
+
 class ProguardView(object):
 
     def __init__(self):
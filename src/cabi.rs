@@ -1,17 +1,22 @@
 use std::ptr;
 use std::mem;
+use std::str;
 use std::slice;
 use std::panic;
 use std::ffi::{CStr, OsStr};
-use std::borrow::Cow;
-use std::os::raw::{c_int, c_uint, c_char};
+use std::sync::Arc;
+use std::io::{self, Write, BufWriter};
+use std::os::raw::{c_int, c_uint, c_char, c_void};
 use std::os::unix::ffi::OsStrExt;
 
 use proguard::MappingView;
 use sourcemap::Error as SourceMapError;
 use errors::{Error, ErrorKind, Result};
-use unified::{View, TokenMatch, Index, ViewOrIndex};
-use memdb::DumpOptions;
+use unified::{View, TokenMatch, Index, Builder, ViewOrIndex, SourceContents, LookupOptions, Bias,
+              Frame};
+use memdb::{DumpOptions, SourceContentsCodec};
+use discovery::{discover_references, SourceMapRef};
+use resolver::{SectionResolver, DirectoryResolver};
 
 
 fn resultbox<T>(val: T) -> Result<*mut T> {
@@ -31,6 +36,48 @@ pub struct Token {
     pub src: *const u8,
     pub src_len: c_uint,
     pub src_id: c_uint,
+    pub dst_end_col: c_uint,
+    pub exact: c_int,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct IndexSection {
+    pub offset_line: c_uint,
+    pub offset_col: c_uint,
+    pub url: *const u8,
+    pub url_len: c_uint,
+    pub token_count: c_uint,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct CFrame {
+    pub line: c_uint,
+    pub col: c_uint,
+    pub function_name: *const c_char,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct SymbolicatedFrame {
+    pub found: c_int,
+    pub token: Token,
+    pub function_name: *const u8,
+    pub function_name_len: c_uint,
+    pub context: *mut u8,
+    pub context_len: c_uint,
+    pub pre_context_count: c_uint,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct SourceReferences {
+    pub source_map_url: *mut u8,
+    pub source_map_url_len: c_uint,
+    pub source_map_inline: c_int,
+    pub debug_id: *mut u8,
+    pub debug_id_len: c_uint,
 }
 
 #[derive(Debug)]
@@ -41,6 +88,88 @@ pub struct Str {
 }
 
 
+/// Callback that receives the data written by `lsm_view_dump_memdb_to_callback`.
+///
+/// A non-zero return value aborts the dump.
+pub type WriteCallback = unsafe extern "C" fn(
+    data: *const u8, len: usize, userdata: *mut c_void) -> c_int;
+
+/// Callback that supplies the map of an index section with a `url`.
+///
+/// Returns 1 and sets `data_out` and `len_out` if the section was resolved
+/// and 0 if it was not found.  The data has to stay valid until the call
+/// that flattens the index returns.  Other return values abort flattening.
+pub type ResolveCallback = unsafe extern "C" fn(
+    url: *const u8, url_len: usize, data_out: *mut *const u8, len_out: *mut usize,
+    userdata: *mut c_void) -> c_int;
+
+struct CallbackResolver {
+    callback: ResolveCallback,
+    userdata: *mut c_void,
+}
+
+impl SectionResolver for CallbackResolver {
+    fn resolve(&mut self, url: &str) -> Result<Option<Vec<u8>>> {
+        let mut data: *const u8 = ptr::null();
+        let mut len = 0;
+        match unsafe {
+            (self.callback)(url.as_ptr(), url.len(), &mut data, &mut len, self.userdata)
+        } {
+            0 => Ok(None),
+            1 if !data.is_null() => Ok(Some(unsafe { slice::from_raw_parts(data, len) }.to_vec())),
+            _ => Err(ErrorKind::InternalError("section resolver failed".into()).into()),
+        }
+    }
+}
+
+struct CallbackWriter {
+    callback: WriteCallback,
+    userdata: *mut c_void,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if unsafe { (self.callback)(buf.as_ptr(), buf.len(), self.userdata) } != 0 {
+            Err(io::Error::other("write callback failed"))
+        } else {
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+unsafe fn dump_options<'a>(with_source_contents: c_int, with_names: c_int,
+                           with_reverse_index: c_int, minified_source: *const c_char,
+                           codec: c_int, codec_level: c_int) -> Result<DumpOptions<'a>> {
+    let source_contents_codec = match codec {
+        0 if codec_level >= 0 => SourceContentsCodec::Brotli(codec_level as u32),
+        1 => SourceContentsCodec::None,
+        2 => SourceContentsCodec::Zstd(codec_level),
+        3 if codec_level >= 0 => SourceContentsCodec::Deflate(codec_level as u32),
+        0 | 3 => { return Err(ErrorKind::BadCompressionLevel(codec_level).into()); }
+        _ => {
+            return Err(ErrorKind::InternalError(
+                "unknown source contents codec".into()).into());
+        }
+    };
+    source_contents_codec.validate()?;
+    Ok(DumpOptions {
+        with_source_contents: with_source_contents != 0,
+        with_names: with_names != 0,
+        with_reverse_index: with_reverse_index != 0,
+        minified_source: if minified_source.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(minified_source).to_str()?)
+        },
+        source_contents_codec,
+        debug_id: None,
+    })
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct CError {
@@ -56,10 +185,18 @@ fn get_error_code_from_kind(kind: &ErrorKind) -> c_int {
         ErrorKind::SourceMap(SourceMapError::CannotFlatten(_)) => 4,
         ErrorKind::UnsupportedMemDbVersion => 5,
         ErrorKind::Io(_) => 6,
+        ErrorKind::BadMemDb(_) => 7,
+        ErrorKind::ChecksumMismatch(_) => 8,
+        ErrorKind::UnsupportedByteOrder => 9,
+        ErrorKind::BadDataUrl(_) => 10,
+        ErrorKind::UnsupportedMediaType(_) => 11,
+        ErrorKind::NoInlineSourceMap => 12,
+        ErrorKind::UnknownId(..) => 13,
         ErrorKind::TooManySources => 20,
         ErrorKind::TooManyNames => 21,
         ErrorKind::LocationOverflow => 22,
         ErrorKind::AlreadyMemDb => 23,
+        ErrorKind::BadCompressionLevel(_) => 24,
         _ => 1,
     }
 }
@@ -80,6 +217,8 @@ unsafe fn set_token<'a>(out: *mut Token, tm: &'a TokenMatch<'a>) {
     };
     (*out).src_len = tm.src.map(|x| x.as_bytes().len()).unwrap_or(0) as c_uint;
     (*out).src_id = tm.src_id;
+    (*out).dst_end_col = tm.dst_end_col.unwrap_or(!0);
+    (*out).exact = if tm.exact { 1 } else { 0 };
 }
 
 
@@ -152,17 +291,44 @@ export!(lsm_view_from_json(bytes: *const u8, len: c_uint) -> Result<*mut View> {
 export!(lsm_view_from_memdb(
     bytes: *const u8, len: c_uint) -> Result<*mut View>
 {
-    // XXX: this currently copies because that's safer.  Consider improving this?
+    // this copies because that's safer.  `lsm_view_from_memdb_borrowed`
+    // avoids the copy.
     resultbox(View::memdb_from_vec(slice::from_raw_parts(
         bytes,
         len as usize
     ).to_vec())?)
 });
 
+export!(lsm_view_from_memdb_validated(
+    bytes: *const u8, len: c_uint) -> Result<*mut View>
+{
+    resultbox(View::memdb_from_vec_validated(slice::from_raw_parts(
+        bytes,
+        len as usize
+    ).to_vec())?)
+});
+
+export!(lsm_view_from_memdb_borrowed(
+    bytes: *const u8, len: c_uint) -> Result<*mut View>
+{
+    // the caller guarantees that the buffer outlives the view
+    resultbox(View::memdb_from_slice(slice::from_raw_parts(bytes, len as usize))?)
+});
+
+export!(lsm_view_from_memdb_borrowed_validated(
+    bytes: *const u8, len: c_uint) -> Result<*mut View>
+{
+    resultbox(View::memdb_from_slice_validated(slice::from_raw_parts(bytes, len as usize))?)
+});
+
 export!(lsm_view_from_memdb_file(path: *const c_char) -> Result<*mut View> {
     resultbox(View::memdb_from_path(CStr::from_ptr(path).to_str()?)?)
 });
 
+export!(lsm_view_from_memdb_file_validated(path: *const c_char) -> Result<*mut View> {
+    resultbox(View::memdb_from_path_validated(CStr::from_ptr(path).to_str()?)?)
+});
+
 export!(lsm_view_free(view: *mut View) {
     if !view.is_null() {
         Box::from_raw(view);
@@ -195,6 +361,43 @@ export!(lsm_view_lookup_token(
     })
 });
 
+export!(lsm_view_lookup_token_with_options(
+        view: *const View, line: c_uint, col: c_uint, bias: c_int, same_line: c_int,
+        max_distance: c_uint, out: *mut Token) -> Result<c_int>
+{
+    let opts = LookupOptions {
+        bias: match bias {
+            0 => Bias::GreatestLowerBound,
+            1 => Bias::LeastUpperBound,
+            _ => {
+                return Err(ErrorKind::InternalError("unknown lookup bias".into()).into());
+            }
+        },
+        same_line: same_line != 0,
+        max_distance: if max_distance == !0 { None } else { Some(max_distance) },
+    };
+    Ok(match (*view).lookup_token_with_options(line, col, &opts) {
+        None => 0,
+        Some(tm) => {
+            set_token(out, &tm);
+            1
+        }
+    })
+});
+
+export!(lsm_view_lookup_original(
+        view: *const View, src_id: c_uint, line: c_uint, col: c_uint,
+        len_out: *mut c_uint) -> Result<*mut Token>
+{
+    let tokens: Vec<Token> = (*view).lookup_original(src_id, line, col).iter().map(|tm| {
+        let mut token = mem::zeroed();
+        set_token(&mut token, tm);
+        token
+    }).collect();
+    *len_out = tokens.len() as c_uint;
+    Ok(Box::into_raw(tokens.into_boxed_slice()) as *mut Token)
+});
+
 export!(lsm_view_get_original_function_name(
         view: *const View, line: c_uint, col: c_uint, minified_name: *const c_char,
         minified_source: *const c_char, name_out: *mut *const c_char) -> Result<c_uint>
@@ -229,16 +432,143 @@ export!(lsm_view_get_source_contents(
         Some(contents) => {
             *len_out = contents.len() as c_uint;
             match contents {
-                Cow::Borrowed(s) => s.as_ptr() as *mut u8,
-                Cow::Owned(val) => {
+                SourceContents::Borrowed(s) => s.as_ptr() as *mut u8,
+                // shared contents are copied out of the source cache.  Use
+                // `lsm_view_get_shared_source_contents` to avoid the copy.
+                SourceContents::Shared(s) => {
+                    *must_free = 1;
+                    Box::into_raw(s.to_string().into_boxed_str()) as *mut u8
+                }
+            }
+        }
+    })
+});
+
+export!(lsm_view_get_shared_source_contents(
+    view: *const View, src_id: c_uint, len_out: *mut c_uint,
+    must_free: *mut c_int) -> Result<*const u8>
+{
+    *must_free = 0;
+    Ok(match (*view).get_source_contents(src_id as u32) {
+        None => ptr::null(),
+        Some(contents) => {
+            *len_out = contents.len() as c_uint;
+            match contents {
+                SourceContents::Borrowed(s) => s.as_ptr(),
+                // the cached contents are returned without copying.  They
+                // hold a reference that has to be released with
+                // `lsm_source_contents_free`.
+                SourceContents::Shared(s) => {
                     *must_free = 1;
-                    Box::into_raw(val.into_boxed_str()) as *mut u8
+                    Arc::into_raw(s) as *const u8
                 }
             }
         }
     })
 });
 
+/// Returns the bytes as NUL terminated buffer that is freed with
+/// `lsm_buffer_free_len`.
+fn into_c_buffer<B: Into<Vec<u8>>>(buf: B, len_out: *mut c_uint) -> *mut u8 {
+    let mut buf = buf.into();
+    unsafe { *len_out = buf.len() as c_uint; }
+    buf.push(0);
+    Box::into_raw(buf.into_boxed_slice()) as *mut u8
+}
+
+/// Frees a buffer created by `into_c_buffer` given its reported length.
+unsafe fn free_c_buffer(buf: *mut u8, len: c_uint) {
+    if !buf.is_null() {
+        let len = len as usize + 1;
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+export!(lsm_view_get_source_line(
+    view: *const View, src_id: c_uint, line: c_uint, len_out: *mut c_uint)
+    -> Result<*mut u8>
+{
+    Ok(match (*view).get_source_line(src_id as u32, line as u32) {
+        Some(line) => into_c_buffer(line, len_out),
+        None => ptr::null_mut(),
+    })
+});
+
+export!(lsm_view_get_source_context(
+    view: *const View, src_id: c_uint, line: c_uint, pre: c_uint, post: c_uint,
+    len_out: *mut c_uint, pre_count_out: *mut c_uint) -> Result<*mut u8>
+{
+    Ok(match (*view).get_source_context(src_id as u32, line as u32, pre as u32,
+                                        post as u32) {
+        Some(ctx) => {
+            *pre_count_out = ctx.pre_lines.len() as c_uint;
+            let mut lines = ctx.pre_lines;
+            lines.push(ctx.line);
+            lines.extend(ctx.post_lines);
+            into_c_buffer(lines.join("\n"), len_out)
+        }
+        None => ptr::null_mut(),
+    })
+});
+
+export!(lsm_view_symbolicate_frames(
+    view: *const View, frames: *const CFrame, count: c_uint,
+    minified_source: *const c_char, context_lines: c_uint) -> Result<*mut SymbolicatedFrame>
+{
+    let mut input = Vec::with_capacity(count as usize);
+    for frame in slice::from_raw_parts(frames, count as usize) {
+        input.push(Frame {
+            line: frame.line,
+            col: frame.col,
+            function_name: if frame.function_name.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(frame.function_name).to_str()?)
+            },
+        });
+    }
+    let minified_source = if minified_source.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(minified_source).to_str()?)
+    };
+
+    let rv: Vec<SymbolicatedFrame> = (*view).symbolicate_frames(
+        &input, minified_source, context_lines).into_iter().map(|frame| {
+        let mut rv: SymbolicatedFrame = mem::zeroed();
+        if let Some(frame) = frame {
+            rv.found = 1;
+            set_token(&mut rv.token, &frame.token);
+            if let Some(name) = frame.function_name {
+                rv.function_name = name.as_ptr();
+                rv.function_name_len = name.len() as c_uint;
+            }
+            if let Some(ctx) = frame.context {
+                rv.pre_context_count = ctx.pre_lines.len() as c_uint;
+                let mut lines = ctx.pre_lines;
+                lines.push(ctx.line);
+                lines.extend(ctx.post_lines);
+                rv.context = into_c_buffer(lines.join("\n"), &mut rv.context_len);
+            }
+        }
+        rv
+    }).collect();
+    Ok(Box::into_raw(rv.into_boxed_slice()) as *mut SymbolicatedFrame)
+});
+
+export!(lsm_symbolicated_frames_free(frames: *mut SymbolicatedFrame, count: c_uint) {
+    if !frames.is_null() {
+        let frames = Box::from_raw(ptr::slice_from_raw_parts_mut(frames, count as usize));
+        for frame in frames.iter() {
+            free_c_buffer(frame.context, frame.context_len);
+        }
+    }
+});
+
+export!(lsm_view_set_source_cache_size(view: *const View, size: c_uint) {
+    (*view).set_source_cache_size(size as usize);
+});
+
 export!(lsm_view_get_source_name(
     view: *const View, src_id: c_uint, len_out: *mut c_uint) -> Result<*const u8>
 {
@@ -251,6 +581,22 @@ export!(lsm_view_get_source_name(
     })
 });
 
+export!(lsm_view_get_debug_id(
+    view: *const View, len_out: *mut c_uint) -> Result<*const u8>
+{
+    Ok(match (*view).get_debug_id() {
+        None => ptr::null(),
+        Some(debug_id) => {
+            *len_out = debug_id.len() as c_uint;
+            debug_id.as_ptr()
+        }
+    })
+});
+
+export!(lsm_view_compose(view: *const View, other: *const View) -> Result<*mut View> {
+    resultbox((*view).compose(&*other)?)
+});
+
 export!(lsm_view_dump_memdb(
     view: *mut View, len_out: *mut c_uint, with_source_contents: c_int,
     with_names: c_int) -> Result<*mut u8>
@@ -258,17 +604,118 @@ export!(lsm_view_dump_memdb(
     let memdb = (*view).dump_memdb(DumpOptions {
         with_source_contents: with_source_contents != 0,
         with_names: with_names != 0,
+        with_reverse_index: true,
+        minified_source: None,
+        source_contents_codec: SourceContentsCodec::default(),
+        debug_id: None,
     })?;
     *len_out = memdb.len() as c_uint;
     Ok(Box::into_raw(memdb.into_boxed_slice()) as *mut u8)
 });
 
+export!(lsm_view_dump_memdb_with_options(
+    view: *mut View, len_out: *mut c_uint, with_source_contents: c_int,
+    with_names: c_int, with_reverse_index: c_int, minified_source: *const c_char, codec: c_int,
+    codec_level: c_int) -> Result<*mut u8>
+{
+    let memdb = (*view).dump_memdb(dump_options(
+        with_source_contents, with_names, with_reverse_index, minified_source, codec,
+        codec_level)?)?;
+    Ok(into_c_buffer(memdb, len_out))
+});
+
+export!(lsm_view_dump_memdb_to_path(
+    view: *mut View, path: *const c_char, with_source_contents: c_int,
+    with_names: c_int, with_reverse_index: c_int, minified_source: *const c_char, codec: c_int,
+    codec_level: c_int) -> Result<c_int>
+{
+    (*view).dump_memdb_to_path(CStr::from_ptr(path).to_str()?, dump_options(
+        with_source_contents, with_names, with_reverse_index, minified_source, codec,
+        codec_level)?)?;
+    Ok(1)
+});
+
+export!(lsm_view_dump_memdb_to_callback(
+    view: *mut View, callback: WriteCallback, userdata: *mut c_void,
+    with_source_contents: c_int, with_names: c_int, with_reverse_index: c_int,
+    minified_source: *const c_char, codec: c_int,
+    codec_level: c_int) -> Result<c_int>
+{
+    let w = BufWriter::with_capacity(65536, CallbackWriter {
+        callback,
+        userdata,
+    });
+    (*view).dump_memdb_to_writer(w, dump_options(
+        with_source_contents, with_names, with_reverse_index, minified_source, codec,
+        codec_level)?)?;
+    Ok(1)
+});
+
+export!(lsm_view_dump_json(view: *mut View, len_out: *mut c_uint) -> Result<*mut u8> {
+    let mut json = vec![];
+    (*view).to_json_writer(&mut json)?;
+    Ok(into_c_buffer(json, len_out))
+});
+
 export!(lsm_buffer_free(buf: *mut u8) {
     if !buf.is_null() {
         Box::from_raw(buf);
     }
 });
 
+export!(lsm_buffer_free_len(buf: *mut u8, len: c_uint) {
+    free_c_buffer(buf, len);
+});
+
+export!(lsm_source_contents_free(buf: *const u8, len: c_uint) {
+    if !buf.is_null() {
+        let contents = ptr::slice_from_raw_parts(buf, len as usize) as *const str;
+        drop(Arc::from_raw(contents));
+    }
+});
+
+export!(lsm_token_array_free(tokens: *mut Token, len: c_uint) {
+    if !tokens.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(tokens, len as usize)));
+    }
+});
+
+export!(lsm_discover_references(
+    source: *const u8, len: c_uint, url: *const c_char,
+    out: *mut SourceReferences) -> Result<c_int>
+{
+    let url = if url.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(url).to_str()?)
+    };
+    let refs = discover_references(slice::from_raw_parts(source, len as usize), url);
+    let out = &mut *out;
+    *out = mem::zeroed();
+    match refs.source_map {
+        Some(SourceMapRef::Url(url)) => {
+            out.source_map_url = into_c_buffer(url, &mut out.source_map_url_len);
+        }
+        Some(SourceMapRef::Inline(url)) => {
+            out.source_map_url = into_c_buffer(url, &mut out.source_map_url_len);
+            out.source_map_inline = 1;
+        }
+        None => {}
+    }
+    if let Some(debug_id) = refs.debug_id {
+        out.debug_id = into_c_buffer(debug_id, &mut out.debug_id_len);
+    }
+    Ok(if out.source_map_url.is_null() && out.debug_id.is_null() { 0 } else { 1 })
+});
+
+export!(lsm_source_references_free(refs: *mut SourceReferences) {
+    let refs = &mut *refs;
+    free_c_buffer(refs.source_map_url, refs.source_map_url_len);
+    refs.source_map_url = ptr::null_mut();
+    free_c_buffer(refs.debug_id, refs.debug_id_len);
+    refs.debug_id = ptr::null_mut();
+});
+
 export!(lsm_index_from_json(bytes: *const u8, len: c_uint) -> Result<*mut Index> {
     resultbox(Index::json_from_slice(slice::from_raw_parts(
         bytes,
@@ -290,24 +737,184 @@ export!(lsm_index_into_view(idx: *mut Index) -> Result<*mut View> {
     resultbox(Box::from_raw(idx).into_view()?)
 });
 
-export!(lsm_view_or_index_from_json(
-    bytes: *const u8, len: c_uint, view_out: *mut *mut View,
-    idx_out: *mut *mut Index) -> Result<c_int> {
-    match ViewOrIndex::from_slice(slice::from_raw_parts(
-        bytes,
-        len as usize
-    ))? {
+unsafe fn set_view_or_index(voi: ViewOrIndex, view_out: *mut *mut View,
+                            idx_out: *mut *mut Index) -> c_int {
+    match voi {
         ViewOrIndex::View(view) => {
             *view_out = Box::into_raw(Box::new(view));
             *idx_out = ptr::null_mut();
-            Ok(1)
+            1
         }
         ViewOrIndex::Index(idx) => {
             *view_out = ptr::null_mut();
             *idx_out = Box::into_raw(Box::new(idx));
-            Ok(2)
+            2
         }
     }
+}
+
+export!(lsm_index_get_section_count(idx: *const Index) -> Result<c_uint> {
+    Ok((*idx).get_section_count())
+});
+
+export!(lsm_index_get_section(
+    idx: *const Index, section_idx: c_uint, out: *mut IndexSection) -> Result<c_int>
+{
+    Ok(match (*idx).get_section(section_idx) {
+        None => 0,
+        Some(section) => {
+            (*out).offset_line = section.offset_line;
+            (*out).offset_col = section.offset_col;
+            match section.url {
+                Some(url) => {
+                    (*out).url = url.as_ptr();
+                    (*out).url_len = url.len() as c_uint;
+                }
+                None => {
+                    (*out).url = ptr::null();
+                    (*out).url_len = 0;
+                }
+            }
+            (*out).token_count = section.token_count.unwrap_or(!0);
+            1
+        }
+    })
+});
+
+export!(lsm_index_lookup_token(
+        idx: *const Index, line: c_uint, col: c_uint, out: *mut Token) -> Result<c_int>
+{
+    Ok(match (*idx).lookup_token(line, col) {
+        None => 0,
+        Some(tm) => {
+            set_token(out, &tm);
+            1
+        }
+    })
+});
+
+export!(lsm_index_dump_memdb(
+    idx: *const Index, len_out: *mut c_uint, with_source_contents: c_int,
+    with_names: c_int, with_reverse_index: c_int, minified_source: *const c_char, codec: c_int,
+    codec_level: c_int) -> Result<*mut u8>
+{
+    let memdb = (*idx).dump_memdb(dump_options(
+        with_source_contents, with_names, with_reverse_index, minified_source, codec,
+        codec_level)?)?;
+    Ok(into_c_buffer(memdb, len_out))
+});
+
+export!(lsm_index_dump_memdb_to_path(
+    idx: *const Index, path: *const c_char, with_source_contents: c_int,
+    with_names: c_int, with_reverse_index: c_int, minified_source: *const c_char, codec: c_int,
+    codec_level: c_int) -> Result<c_int>
+{
+    (*idx).dump_memdb_to_path(CStr::from_ptr(path).to_str()?, dump_options(
+        with_source_contents, with_names, with_reverse_index, minified_source, codec,
+        codec_level)?)?;
+    Ok(1)
+});
+
+export!(lsm_index_flatten_with(
+    idx: *mut Index, callback: ResolveCallback, userdata: *mut c_void) -> Result<*mut View>
+{
+    let mut resolver = CallbackResolver {
+        callback,
+        userdata,
+    };
+    resultbox(Box::from_raw(idx).flatten_with(&mut resolver)?)
+});
+
+export!(lsm_index_flatten_with_directory(
+    idx: *mut Index, path: *const c_char) -> Result<*mut View>
+{
+    let mut resolver = DirectoryResolver::new(OsStr::from_bytes(CStr::from_ptr(path).to_bytes()));
+    resultbox(Box::from_raw(idx).flatten_with(&mut resolver)?)
+});
+
+export!(lsm_view_or_index_from_json(
+    bytes: *const u8, len: c_uint, view_out: *mut *mut View,
+    idx_out: *mut *mut Index) -> Result<c_int> {
+    let voi = ViewOrIndex::from_slice(slice::from_raw_parts(bytes, len as usize))?;
+    Ok(set_view_or_index(voi, view_out, idx_out))
+});
+
+export!(lsm_view_or_index_from_data_url(
+    url: *const u8, len: c_uint, view_out: *mut *mut View,
+    idx_out: *mut *mut Index) -> Result<c_int> {
+    let url = str::from_utf8(slice::from_raw_parts(url, len as usize))?;
+    Ok(set_view_or_index(ViewOrIndex::from_data_url(url)?, view_out, idx_out))
+});
+
+export!(lsm_view_or_index_from_minified_source(
+    source: *const u8, len: c_uint, view_out: *mut *mut View,
+    idx_out: *mut *mut Index) -> Result<c_int> {
+    let voi = ViewOrIndex::from_minified_source(
+        slice::from_raw_parts(source, len as usize))?;
+    Ok(set_view_or_index(voi, view_out, idx_out))
+});
+
+export!(lsm_builder_new(file: *const c_char) -> Result<*mut Builder> {
+    resultbox(Builder::new(if file.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(file).to_str()?)
+    }))
+});
+
+export!(lsm_builder_free(builder: *mut Builder) {
+    if !builder.is_null() {
+        drop(Box::from_raw(builder));
+    }
+});
+
+export!(lsm_builder_set_debug_id(
+    builder: *mut Builder, debug_id: *const c_char) -> Result<c_int>
+{
+    (*builder).set_debug_id(if debug_id.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(debug_id).to_str()?)
+    });
+    Ok(1)
+});
+
+export!(lsm_builder_add_source(
+    builder: *mut Builder, source: *const u8, len: c_uint) -> Result<c_uint>
+{
+    Ok((*builder).add_source(str::from_utf8(slice::from_raw_parts(source, len as usize))?))
+});
+
+export!(lsm_builder_set_source_contents(
+    builder: *mut Builder, src_id: c_uint, contents: *const u8, len: c_uint) -> Result<c_int>
+{
+    let contents = if contents.is_null() {
+        None
+    } else {
+        Some(str::from_utf8(slice::from_raw_parts(contents, len as usize))?)
+    };
+    (*builder).set_source_contents(src_id, contents)?;
+    Ok(1)
+});
+
+export!(lsm_builder_add_name(
+    builder: *mut Builder, name: *const u8, len: c_uint) -> Result<c_uint>
+{
+    Ok((*builder).add_name(str::from_utf8(slice::from_raw_parts(name, len as usize))?))
+});
+
+export!(lsm_builder_add_token(
+    builder: *mut Builder, dst_line: c_uint, dst_col: c_uint, src_line: c_uint,
+    src_col: c_uint, src_id: c_uint, name_id: c_uint) -> Result<c_int>
+{
+    (*builder).add_token(dst_line, dst_col, src_line, src_col,
+                         Some(src_id).filter(|&x| x != !0),
+                         Some(name_id).filter(|&x| x != !0))?;
+    Ok(1)
+});
+
+export!(lsm_builder_into_view(builder: *mut Builder) -> Result<*mut View> {
+    resultbox(Box::from_raw(builder).into_view())
 });
 
 export!(lsm_proguard_mapping_from_bytes(bytes: *const u8, len: c_uint)
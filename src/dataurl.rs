@@ -0,0 +1,71 @@
+use base64;
+use url::percent_encoding::percent_decode;
+
+use discovery::{discover_references, SourceMapRef};
+use errors::{ErrorKind, Result};
+
+
+/// The media types that can hold a source map.
+const SOURCE_MAP_MEDIA_TYPES: &[&str] = &[
+    "application/json",
+    "text/json",
+];
+
+fn is_supported_charset(charset: &str) -> bool {
+    charset.eq_ignore_ascii_case("utf-8") ||
+        charset.eq_ignore_ascii_case("utf8") ||
+        charset.eq_ignore_ascii_case("us-ascii")
+}
+
+/// Decodes the payload of a `data:` URL holding a source map.
+///
+/// Both base64 and percent-encoded payloads are supported.  Media types
+/// other than JSON and charsets other than UTF-8 are rejected with
+/// `UnsupportedMediaType`.
+pub fn decode_data_url(url: &str) -> Result<Vec<u8>> {
+    let rest = match url.get(..5) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("data:") => &url[5..],
+        _ => return Err(ErrorKind::BadDataUrl("not a data URL".into()).into()),
+    };
+    let comma = rest.find(',').ok_or_else(|| {
+        ErrorKind::BadDataUrl("missing data separator".into())
+    })?;
+    let (header, data) = (&rest[..comma], &rest[comma + 1..]);
+
+    let mut params = header.split(';').map(|x| x.trim());
+    let media_type = params.next().unwrap_or("");
+    if !SOURCE_MAP_MEDIA_TYPES.iter().any(|x| x.eq_ignore_ascii_case(media_type)) {
+        return Err(ErrorKind::UnsupportedMediaType(header.to_string()).into());
+    }
+
+    let mut is_base64 = false;
+    for param in params {
+        if param.eq_ignore_ascii_case("base64") {
+            is_base64 = true;
+        } else if let Some(value) = param.split_once('=').and_then(|(key, value)| {
+            if key.trim().eq_ignore_ascii_case("charset") { Some(value) } else { None }
+        }) {
+            if !is_supported_charset(value.trim_matches('"')) {
+                return Err(ErrorKind::UnsupportedMediaType(header.to_string()).into());
+            }
+        }
+    }
+
+    if is_base64 {
+        // base64 payloads are occasionally percent-encoded as well
+        let data: Vec<u8> = percent_decode(data.as_bytes()).collect();
+        base64::decode_ws(&String::from_utf8_lossy(&data)).map_err(|_| {
+            ErrorKind::BadDataUrl("invalid base64 data".into()).into()
+        })
+    } else {
+        Ok(percent_decode(data.as_bytes()).collect())
+    }
+}
+
+/// Finds and decodes the inline source map of a minified file.
+pub fn decode_inline_source_map(source: &[u8]) -> Result<Vec<u8>> {
+    match discover_references(source, None).source_map {
+        Some(SourceMapRef::Inline(url)) => decode_data_url(&url),
+        _ => Err(ErrorKind::NoInlineSourceMap.into()),
+    }
+}
@@ -0,0 +1,70 @@
+use std::str::from_utf8;
+
+use regex::bytes::Regex;
+use url::Url;
+
+
+// the comments have to start a line so that they are not picked up from
+// string literals or code.
+lazy_static! {
+    static ref SOURCE_MAPPING_URL_RE: Regex = Regex::new(
+        r#"(?m)^[ \t]*(?://|/\*)[@#][ \t]+sourceMappingURL=[ \t]*([^\s'"]+)"#).unwrap();
+    static ref DEBUG_ID_RE: Regex = Regex::new(
+        r#"(?m)^[ \t]*(?://|/\*)[@#][ \t]+debugId=[ \t]*([^\s'"]+)"#).unwrap();
+}
+
+/// A reference to a source map found in a minified file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceMapRef {
+    /// The URL of the source map.
+    Url(String),
+    /// A `data:` URL that contains the source map.
+    Inline(String),
+}
+
+/// The references found in the comments of a minified file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceReferences {
+    pub source_map: Option<SourceMapRef>,
+    pub debug_id: Option<String>,
+}
+
+/// Returns the value of the last comment matching the regex.
+fn find_last_comment<'a>(re: &Regex, source: &'a [u8]) -> Option<&'a str> {
+    let value = re.captures_iter(source).filter_map(|caps| caps.get(1)).last()?;
+    let value = from_utf8(value.as_bytes()).ok()?;
+    // css comments can be closed without a space before them
+    Some(value.strip_suffix("*/").unwrap_or(value)).filter(|x| !x.is_empty())
+}
+
+/// Resolves a URL relative to the URL of the minified file.
+///
+/// URLs that cannot be resolved are returned as they are.
+pub fn resolve_url(base: &str, url: &str) -> String {
+    Url::parse(base)
+        .and_then(|base| base.join(url))
+        .map(|url| url.into_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+/// Scans a minified JavaScript or CSS file for source map references.
+///
+/// This understands `//# sourceMappingURL=` and `/*# sourceMappingURL= */`
+/// comments including the legacy `//@` forms as well as `//# debugId=`
+/// comments.  Only comments at the start of a line are considered and
+/// if the comment is repeated the last one wins.  Relative URLs are
+/// resolved against `url` if it is given.
+pub fn discover_references(source: &[u8], url: Option<&str>) -> SourceReferences {
+    SourceReferences {
+        source_map: find_last_comment(&SOURCE_MAPPING_URL_RE, source).map(|value| {
+            if value.starts_with("data:") {
+                SourceMapRef::Inline(value.to_string())
+            } else if let Some(base) = url {
+                SourceMapRef::Url(resolve_url(base, value))
+            } else {
+                SourceMapRef::Url(value.to_string())
+            }
+        }),
+        debug_id: find_last_comment(&DEBUG_ID_RE, source).map(|x| x.to_string()),
+    }
+}
@@ -21,8 +21,31 @@ error_chain! {
         UnsupportedMemDbVersion {
             description("Unsupported memdb version")
         }
-        BadMemDb {
+        UnsupportedByteOrder {
+            description("Memdb was written with an unsupported byte order")
+        }
+        BadMemDb(msg: String) {
             description("Bad memdb data")
+            display("Bad memdb data: {}", &msg)
+        }
+        ChecksumMismatch(section: String) {
+            description("Memdb checksum mismatch")
+            display("Memdb checksum mismatch in {}", &section)
+        }
+        BadDataUrl(msg: String) {
+            description("Bad data URL")
+            display("Bad data URL: {}", &msg)
+        }
+        UnsupportedMediaType(media_type: String) {
+            description("Unsupported media type for source map")
+            display("Unsupported media type for source map: {}", &media_type)
+        }
+        NoInlineSourceMap {
+            description("No inline source map found in file")
+        }
+        UnknownId(kind: String, id: u32) {
+            description("Unknown id passed to builder")
+            display("Unknown {} id {}", &kind, id)
         }
         TooManySources {
             description("Too many sources in the file for memdb")
@@ -36,5 +59,9 @@ error_chain! {
         AlreadyMemDb {
             description("Cannot dump memdb from memdb view")
         }
+        BadCompressionLevel(level: i32) {
+            description("Unsupported compression level for source contents")
+            display("Unsupported compression level for source contents: {}", level)
+        }
     }
 }
@@ -4,16 +4,32 @@ extern crate sourcemap;
 extern crate proguard;
 extern crate memmap;
 extern crate brotli2;
+extern crate crc32fast;
+extern crate flate2;
+extern crate zstd;
 extern crate varinteger;
+extern crate regex;
+extern crate url;
+extern crate base64;
+extern crate serde_json;
 
 #[macro_use]
 extern crate error_chain;
+#[macro_use]
+extern crate lazy_static;
 
 mod errors;
+mod utils;
+mod discovery;
+mod dataurl;
+mod resolver;
 pub mod memdb;
 
 pub use errors::{Error, ErrorKind, Result};
-pub use unified::{View, Index, TokenMatch};
+pub use resolver::{SectionResolver, DirectoryResolver};
+pub use discovery::{discover_references, resolve_url, SourceMapRef, SourceReferences};
+pub use unified::{View, Index, ViewOrIndex, Builder, IndexSection, TokenMatch, SourceContents,
+                  SourceContext, LookupOptions, Bias, Frame, SymbolicatedFrame};
 
 // unified interface
 mod unified;
@@ -1,30 +1,91 @@
 use std::str::from_utf8;
 use std::mem;
 use std::fmt;
-use std::slice;
 use std::io;
 use std::path::Path;
-use std::ptr::copy_nonoverlapping;
+use std::marker::PhantomData;
 use std::io::{Read, Write, Seek, SeekFrom};
 use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
 use memmap::{Mmap, Protection};
 
 use varinteger;
-use sourcemap::{RawToken, SourceMap};
+use sourcemap::{RawToken, SourceMap, SourceMapIndex, Error as SourceMapError};
 use brotli2::read::{BrotliEncoder, BrotliDecoder};
+use crc32fast::Hasher;
+use flate2::Compression;
+use flate2::read::{DeflateEncoder, DeflateDecoder};
+use zstd;
 
 use errors::{ErrorKind, Result};
+use utils::{SourceCursor, LineOffsets};
 
 
+/// The memdb format version that is written by this library.
+///
+/// All values in memdb files are stored in little-endian byte order
+/// independent of the host.
+pub const MEMDB_VERSION: u32 = 2;
+
+/// The default size in bytes of the decompressed source contents cache.
+pub const DEFAULT_SOURCE_CACHE_SIZE: usize = 32 * 1024 * 1024;
+
+
+/// An index item of the version 1 format.
+///
+/// This packs line and column information into 31 bits per location
+/// which limits the files that can be represented.
 #[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
 pub struct IndexItem {
     pub packed_locinfo: u64,
     pub ids: u32,
 }
 
+/// An index item of the version 2 format.
+///
+/// Unlike `IndexItem` this stores full `u32` line and column values and
+/// only holds the locations of a token.  The source and name ids
+/// are stored in a separate column (see `IdEncoding`) so that the binary
+/// search in `lookup_token` only has to touch the locations.
+#[derive(Debug, Copy, Clone)]
+pub struct LocIndexItem {
+    pub dst_line: u32,
+    pub dst_col: u32,
+    pub src_line: u32,
+    pub src_col: u32,
+}
+
+/// An item of the id column for `IdEncoding::Wide`.
+#[derive(Debug, Copy, Clone)]
+pub struct WideIds {
+    pub src_id: u32,
+    pub name_id: u32,
+}
+
+/// An item of the function name section.
+///
+/// Each item marks a token whose minified identifier directly follows a
+/// `function` keyword in the minified source.  `minified_name` is the
+/// offset of the minified identifier in the file, `name_id` the original
+/// name of the token.
+#[derive(Debug, Copy, Clone)]
+pub struct FunctionNameItem {
+    pub token_idx: u32,
+    pub minified_name: u32,
+    pub name_id: u32,
+}
+
+/// Describes how the source and name ids of tokens are stored.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IdEncoding {
+    /// 14 bit source ids and 18 bit name ids packed into a single `u32`.
+    Packed,
+    /// Full `u32` source and name ids (`WideIds`).
+    Wide,
+}
+
 #[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
 pub struct MapHead {
     pub version: u32,
     pub index_size: u32,
@@ -34,11 +95,64 @@ pub struct MapHead {
     pub sources_count: u32,
     pub source_contents_start: u32,
     pub source_contents_count: u32,
+    // the following fields only exist in version 2 and later.
+    pub ids_start: u32,
+    pub ids_encoding: u32,
+    pub function_names_start: u32,
+    pub function_names_count: u32,
+    // CRC32 checksums of the header (with this field zeroed) and of each
+    // section (see `MemDb::verify_checksums`).
+    pub header_checksum: u32,
+    pub index_checksum: u32,
+    pub ids_checksum: u32,
+    pub strings_checksum: u32,
+    pub names_checksum: u32,
+    pub sources_checksum: u32,
+    pub function_names_checksum: u32,
+    pub source_contents_checksum: u32,
+    // zeroed in version 1 files which always use brotli.
+    pub source_contents_codec: u32,
+    pub source_contents_level: u32,
+    // the line offsets hold the line starts of each source with contents.
+    pub line_offsets_start: u32,
+    pub line_offsets_count: u32,
+    pub line_offsets_checksum: u32,
+    // the reverse index holds token indexes sorted by original location.
+    pub reverse_index_start: u32,
+    pub reverse_index_count: u32,
+    pub reverse_index_checksum: u32,
+    // the offset of the debug id in the strings section or `!0` if there
+    // is none.
+    pub debug_id: u32,
 }
 
-pub struct DumpOptions {
+/// The compression used for source contents in a memdb.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SourceContentsCodec {
+    /// Brotli with the given quality (0 to 11).
+    Brotli(u32),
+    /// Source contents are stored uncompressed.
+    None,
+    /// Zstandard with the given level (1 to 22).
+    Zstd(i32),
+    /// Raw deflate with the given level (0 to 9).
+    Deflate(u32),
+}
+
+pub struct DumpOptions<'a> {
     pub with_source_contents: bool,
     pub with_names: bool,
+    /// If enabled a reverse index for `MemDb::get_original_line_tokens`
+    /// is written.
+    pub with_reverse_index: bool,
+    /// If the minified source is provided the information needed for
+    /// `get_original_function_name` is precomputed and embedded.
+    pub minified_source: Option<&'a str>,
+    /// The codec used to compress the source contents.
+    pub source_contents_codec: SourceContentsCodec,
+    /// The debug id to embed.  Views fill in their own debug id if this
+    /// is not set.
+    pub debug_id: Option<&'a str>,
 }
 
 enum Backing<'a> {
@@ -47,7 +161,21 @@ enum Backing<'a> {
 }
 
 pub struct MemDb<'a> {
-    backing: Backing<'a>
+    backing: Backing<'a>,
+    head: MapHead,
+    source_cache: Mutex<SourceCache>,
+}
+
+/// A bounded cache of decompressed source contents.
+///
+/// The contents are shared so they stay valid for their users after
+/// they were evicted from the cache.
+struct SourceCache {
+    max_size: usize,
+    size: usize,
+    entries: HashMap<u32, Arc<str>>,
+    // source ids from the least to the most recently used
+    lru: VecDeque<u32>,
 }
 
 pub struct Token<'a> {
@@ -56,12 +184,85 @@ pub struct Token<'a> {
 }
 
 
-fn verify_version<'a>(rv: MemDb<'a>) -> Result<MemDb<'a>> {
-    if rv.header()?.version != 1 {
-        Err(ErrorKind::UnsupportedMemDbVersion.into())
-    } else {
-        Ok(rv)
+trait IndexEntry {
+    fn dst(&self) -> (u32, u32);
+}
+
+/// A fixed size record in a memdb file.
+///
+/// Records are encoded field by field in little-endian byte order so that
+/// files can be shared between hosts and are read without unaligned loads.
+trait Record: Sized {
+    /// The size of an encoded record in bytes.
+    const SIZE: usize;
+
+    /// Decodes a record from exactly `SIZE` bytes.
+    fn decode(buf: &[u8]) -> Self;
+
+    /// Encodes the record into a writer.
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+}
+
+/// A section of records in a memdb buffer.
+struct Records<'a, T> {
+    data: &'a [u8],
+    ty: PhantomData<T>,
+}
+
+
+fn bad_memdb<T>(msg: &str) -> Result<T> {
+    Err(ErrorKind::BadMemDb(msg.to_string()).into())
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_header(buffer: &[u8]) -> Result<MapHead> {
+    if buffer.len() < u32::SIZE {
+        return bad_memdb("header is truncated");
     }
+    let version = read_u32(buffer, 0);
+    let size = match MapHead::size_for_version(version) {
+        Some(size) => size,
+        None => {
+            // older versions of this library wrote files in host byte
+            // order.  Files from big-endian hosts show up with a swapped
+            // version number.
+            if MapHead::size_for_version(version.swap_bytes()).is_some() {
+                return Err(ErrorKind::UnsupportedByteOrder.into());
+            }
+            return Err(ErrorKind::UnsupportedMemDbVersion.into());
+        }
+    };
+    if buffer.len() < size {
+        return bad_memdb("header is truncated");
+    }
+
+    // older headers are a prefix of the current one.  The fields they do
+    // not have stay zeroed.
+    let mut buf = [0u8; MapHead::SIZE];
+    buf[..size].copy_from_slice(&buffer[..size]);
+    Ok(MapHead::decode(&buf))
+}
+
+fn decode_varint(buffer: &[u8], offset: usize) -> Option<(u64, usize)> {
+    let mut val = 0u64;
+    for (idx, &byte) in buffer.get(offset..)?.iter().take(10).enumerate() {
+        val |= ((byte & 0x7f) as u64) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return Some((val, idx + 1));
+        }
+    }
+    None
 }
 
 fn pack_loc_shape(line: u32, col: u32) -> Result<(u8, u32)> {
@@ -90,18 +291,282 @@ fn unpack_loc_shape(shape: u8, packed: u32) -> (u32, u32) {
 }
 
 
-impl IndexItem {
+fn pack_ids(src_id: u32, name_id: u32) -> Result<u32> {
+    let packed_src_id : u32 = src_id & 0x3fff;
+    let packed_name_id : u32 = name_id & 0x3ffff;
+    if src_id != !0 && packed_src_id >= 0x3fff {
+        return Err(ErrorKind::TooManySources.into());
+    }
+    if name_id != !0 && packed_name_id >= 0x3ffff {
+        return Err(ErrorKind::TooManyNames.into());
+    }
+    Ok((packed_src_id << 18) | packed_name_id)
+}
 
-    pub fn new(raw: &RawToken) -> Result<IndexItem> {
-        let packed_src_id : u32 = raw.src_id & 0x3fff;
-        let packed_name_id : u32 = raw.name_id & 0x3ffff;
-        if raw.src_id != !0 && packed_src_id >= 0x3fff {
-            return Err(ErrorKind::TooManySources.into());
+fn unpack_src_id(ids: u32) -> u32 {
+    let src_id = ids >> 18;
+    if src_id == 0x3fff {
+        !0
+    } else {
+        src_id
+    }
+}
+
+fn unpack_name_id(ids: u32) -> u32 {
+    let name_id = ids & 0x3ffff;
+    if name_id == 0x3ffff {
+        !0
+    } else {
+        name_id
+    }
+}
+
+fn lookup_index<T: IndexEntry + Record>(index: &Records<T>, line: u32, col: u32)
+    -> Option<usize>
+{
+    let mut low = 0;
+    let mut high = index.len();
+
+    while low < high {
+        let mid = (low + high) / 2;
+        if (line, col) < index.get(mid)?.dst() {
+            high = mid;
+        } else {
+            low = mid + 1;
         }
-        if raw.name_id != !0 && packed_name_id >= 0x3ffff {
-            return Err(ErrorKind::TooManyNames.into());
+    }
+
+    if low > 0 && low <= index.len() {
+        Some(low - 1)
+    } else {
+        None
+    }
+}
+
+
+/// Implements `Record` for structs that only consist of `u32` fields.
+macro_rules! impl_u32_record {
+    ($ty:ident, [$($field:ident),*]) => {
+        impl Record for $ty {
+            const SIZE: usize = [$(stringify!($field)),*].len() * 4;
+
+            fn decode(buf: &[u8]) -> $ty {
+                let mut offset = 0;
+                let mut next = || {
+                    offset += 4;
+                    read_u32(buf, offset - 4)
+                };
+                $ty {
+                    $($field: next()),*
+                }
+            }
+
+            fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                $(w.write_all(&self.$field.to_le_bytes())?;)*
+                Ok(())
+            }
         }
+    }
+}
 
+impl_u32_record!(MapHead, [
+    version, index_size, names_start, names_count, sources_start,
+    sources_count, source_contents_start, source_contents_count, ids_start,
+    ids_encoding, function_names_start, function_names_count,
+    header_checksum, index_checksum, ids_checksum, strings_checksum,
+    names_checksum, sources_checksum, function_names_checksum,
+    source_contents_checksum, source_contents_codec, source_contents_level,
+    line_offsets_start, line_offsets_count, line_offsets_checksum,
+    reverse_index_start, reverse_index_count, reverse_index_checksum,
+    debug_id
+]);
+impl_u32_record!(LocIndexItem, [dst_line, dst_col, src_line, src_col]);
+impl_u32_record!(WideIds, [src_id, name_id]);
+impl_u32_record!(FunctionNameItem, [token_idx, minified_name, name_id]);
+
+impl Record for u32 {
+    const SIZE: usize = 4;
+
+    fn decode(buf: &[u8]) -> u32 {
+        read_u32(buf, 0)
+    }
+
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Record for IndexItem {
+    const SIZE: usize = 12;
+
+    fn decode(buf: &[u8]) -> IndexItem {
+        IndexItem {
+            packed_locinfo: read_u64(buf, 0),
+            ids: read_u32(buf, 8),
+        }
+    }
+
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.packed_locinfo.to_le_bytes())?;
+        w.write_all(&self.ids.to_le_bytes())
+    }
+}
+
+impl<'a, T: Record> Records<'a, T> {
+
+    fn new(data: &'a [u8]) -> Records<'a, T> {
+        Records {
+            data,
+            ty: PhantomData,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len() / T::SIZE
+    }
+
+    fn get(&self, idx: usize) -> Option<T> {
+        let start = idx.checked_mul(T::SIZE)?;
+        self.data.get(start..start.checked_add(T::SIZE)?).map(T::decode)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = T> + 'a where T: 'a {
+        self.data.chunks(T::SIZE).map(T::decode)
+    }
+}
+
+impl MapHead {
+
+    /// Returns the size of the header in a file of the given version.
+    pub fn size_for_version(version: u32) -> Option<usize> {
+        match version {
+            1 => Some(32),
+            2 => Some(MapHead::SIZE),
+            _ => None,
+        }
+    }
+
+    /// Returns the encoding of the id column.
+    ///
+    /// Version 1 files always pack the ids into the index items.
+    pub fn id_encoding(&self) -> Option<IdEncoding> {
+        if self.version < 2 {
+            return Some(IdEncoding::Packed);
+        }
+        match self.ids_encoding {
+            0 => Some(IdEncoding::Packed),
+            1 => Some(IdEncoding::Wide),
+            _ => None,
+        }
+    }
+
+    /// Returns the codec of the source contents.
+    pub fn source_contents_codec(&self) -> Option<SourceContentsCodec> {
+        SourceContentsCodec::from_raw(self.source_contents_codec, self.source_contents_level)
+    }
+
+    /// Calculates the checksum of the header.
+    pub fn calculate_checksum(&self) -> u32 {
+        let mut head = *self;
+        head.header_checksum = 0;
+        let mut buf = Vec::with_capacity(MapHead::SIZE);
+        head.encode(&mut buf).unwrap();
+        let mut hasher = Hasher::new();
+        hasher.update(&buf);
+        hasher.finalize()
+    }
+}
+
+impl Default for SourceContentsCodec {
+    fn default() -> SourceContentsCodec {
+        SourceContentsCodec::Brotli(4)
+    }
+}
+
+impl SourceContentsCodec {
+
+    fn from_raw(codec: u32, level: u32) -> Option<SourceContentsCodec> {
+        match codec {
+            0 => Some(SourceContentsCodec::Brotli(level)),
+            1 => Some(SourceContentsCodec::None),
+            2 => Some(SourceContentsCodec::Zstd(level as i32)),
+            3 => Some(SourceContentsCodec::Deflate(level)),
+            _ => None,
+        }
+    }
+
+    /// Checks that the compression level is supported by the codec.
+    pub fn validate(self) -> Result<()> {
+        let ok = match self {
+            SourceContentsCodec::Brotli(level) => level <= 11,
+            SourceContentsCodec::None => true,
+            SourceContentsCodec::Zstd(level) => (1..=22).contains(&level),
+            SourceContentsCodec::Deflate(level) => level <= 9,
+        };
+        if ok {
+            Ok(())
+        } else {
+            let level = match self {
+                SourceContentsCodec::Brotli(level) |
+                SourceContentsCodec::Deflate(level) => level as i32,
+                SourceContentsCodec::Zstd(level) => level,
+                SourceContentsCodec::None => 0,
+            };
+            Err(ErrorKind::BadCompressionLevel(level).into())
+        }
+    }
+
+    fn to_raw(self) -> (u32, u32) {
+        match self {
+            SourceContentsCodec::Brotli(level) => (0, level),
+            SourceContentsCodec::None => (1, 0),
+            SourceContentsCodec::Zstd(level) => (2, level as u32),
+            SourceContentsCodec::Deflate(level) => (3, level),
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut rv = vec![];
+        match self {
+            SourceContentsCodec::Brotli(level) => {
+                BrotliEncoder::new(data, level).read_to_end(&mut rv)?;
+            }
+            SourceContentsCodec::None => rv.extend_from_slice(data),
+            SourceContentsCodec::Zstd(level) => {
+                rv = zstd::encode_all(data, level)?;
+            }
+            SourceContentsCodec::Deflate(level) => {
+                DeflateEncoder::new(data, Compression::new(level)).read_to_end(&mut rv)?;
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Decompresses source contents.  Invalid UTF-8 is replaced.
+    fn decompress(self, data: &[u8]) -> io::Result<String> {
+        let mut rv = vec![];
+        match self {
+            SourceContentsCodec::Brotli(_) => {
+                BrotliDecoder::new(data).read_to_end(&mut rv)?;
+            }
+            SourceContentsCodec::None => rv.extend_from_slice(data),
+            SourceContentsCodec::Zstd(_) => {
+                zstd::Decoder::new(data)?.read_to_end(&mut rv)?;
+            }
+            SourceContentsCodec::Deflate(_) => {
+                DeflateDecoder::new(data).read_to_end(&mut rv)?;
+            }
+        }
+        Ok(String::from_utf8(rv).unwrap_or_else(|err| {
+            String::from_utf8_lossy(err.as_bytes()).into_owned()
+        }))
+    }
+}
+
+impl IndexItem {
+
+    pub fn new(raw: &RawToken) -> Result<IndexItem> {
+        let ids = pack_ids(raw.src_id, raw.name_id)?;
         let (shape_dst, packed_dst) = pack_loc_shape(raw.dst_line, raw.dst_col)?;
         let (shape_src, packed_src) = pack_loc_shape(raw.src_line, raw.src_col)?;
 
@@ -112,24 +577,16 @@ impl IndexItem {
                 ((packed_dst as u64) << 31) |
                 (packed_src as u64)
             ),
-            ids: (packed_src_id << 18) | packed_name_id,
+            ids,
         })
     }
 
     pub fn src_id(&self) -> u32 {
-        let mut src_id : u32 = self.ids >> 18;
-        if src_id == 0x3fff {
-            src_id = !0;
-        }
-        src_id
+        unpack_src_id(self.ids)
     }
 
     pub fn name_id(&self) -> u32 {
-        let mut name_id : u32 = self.ids & 0x3ffff;
-        if name_id == 0x3ffff {
-            name_id = !0;
-        }
-        name_id
+        unpack_name_id(self.ids)
     }
 
     pub fn dst_line(&self) -> u32 {
@@ -153,14 +610,125 @@ impl IndexItem {
     }
 }
 
+impl IndexEntry for IndexItem {
+    fn dst(&self) -> (u32, u32) {
+        (self.dst_line(), self.dst_col())
+    }
+}
+
+impl IndexItem {
+
+    fn raw_token(&self) -> RawToken {
+        RawToken {
+            dst_line: self.dst_line(),
+            dst_col: self.dst_col(),
+            src_line: self.src_line(),
+            src_col: self.src_col(),
+            src_id: self.src_id(),
+            name_id: self.name_id(),
+        }
+    }
+}
+
+impl LocIndexItem {
+
+    pub fn new(raw: &RawToken) -> LocIndexItem {
+        LocIndexItem {
+            dst_line: raw.dst_line,
+            dst_col: raw.dst_col,
+            src_line: raw.src_line,
+            src_col: raw.src_col,
+        }
+    }
+}
+
+impl IndexEntry for LocIndexItem {
+    fn dst(&self) -> (u32, u32) {
+        (self.dst_line, self.dst_col)
+    }
+}
+
+impl SourceCache {
+
+    fn new(max_size: usize) -> SourceCache {
+        SourceCache {
+            max_size,
+            size: 0,
+            entries: HashMap::new(),
+            lru: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, src_id: u32) -> Option<Arc<str>> {
+        let rv = self.entries.get(&src_id)?.clone();
+        if let Some(pos) = self.lru.iter().position(|&x| x == src_id) {
+            self.lru.remove(pos);
+        }
+        self.lru.push_back(src_id);
+        Some(rv)
+    }
+
+    fn insert(&mut self, src_id: u32, contents: Arc<str>) {
+        if contents.len() > self.max_size || self.entries.contains_key(&src_id) {
+            return;
+        }
+        self.size += contents.len();
+        self.entries.insert(src_id, contents);
+        self.lru.push_back(src_id);
+        self.evict();
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let src_id = match self.lru.pop_front() {
+                Some(src_id) => src_id,
+                None => break,
+            };
+            if let Some(contents) = self.entries.remove(&src_id) {
+                self.size -= contents.len();
+            }
+        }
+    }
+}
+
+impl<'a> Backing<'a> {
+
+    #[inline(always)]
+    fn buffer(&self) -> &[u8] {
+        match *self {
+            Backing::Buf(ref buf) => buf,
+            Backing::Mmap(ref mmap) => unsafe { mmap.as_slice() }
+        }
+    }
+}
+
 impl<'a> MemDb<'a> {
 
-    pub fn from_cow(cow: Cow<'a, [u8]>) -> Result<MemDb<'a>> {
-        verify_version(MemDb {
-            backing: Backing::Buf(cow),
+    fn from_backing(backing: Backing<'a>) -> Result<MemDb<'a>> {
+        let head = read_header(backing.buffer())?;
+        Ok(MemDb {
+            backing,
+            head,
+            source_cache: Mutex::new(SourceCache::new(DEFAULT_SOURCE_CACHE_SIZE)),
         })
     }
 
+    pub fn from_cow(cow: Cow<'a, [u8]>) -> Result<MemDb<'a>> {
+        MemDb::from_backing(Backing::Buf(cow))
+    }
+
+    /// Like `from_cow` but also validates the memdb.
+    pub fn from_cow_validated(cow: Cow<'a, [u8]>) -> Result<MemDb<'a>> {
+        let rv = MemDb::from_cow(cow)?;
+        rv.validate()?;
+        Ok(rv)
+    }
+
     pub fn from_slice(buffer: &'a [u8]) -> Result<MemDb<'a>> {
         MemDb::from_cow(Cow::Borrowed(buffer))
     }
@@ -169,158 +737,540 @@ impl<'a> MemDb<'a> {
         MemDb::from_cow(Cow::Owned(buffer))
     }
 
-    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<MemDb<'a>> {
-        let mmap = Mmap::open_path(path, Protection::Read)?;
-        verify_version(MemDb {
-            backing: Backing::Mmap(mmap),
-        })
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<MemDb<'a>> {
+        let mmap = Mmap::open_path(path, Protection::Read)?;
+        MemDb::from_backing(Backing::Mmap(mmap))
+    }
+
+    /// Like `from_path` but also validates the memdb.
+    pub fn from_path_validated<P: AsRef<Path>>(path: P) -> Result<MemDb<'a>> {
+        let rv = MemDb::from_path(path)?;
+        rv.validate()?;
+        Ok(rv)
+    }
+
+    /// Verifies the structure of the memdb.
+    ///
+    /// This checks the checksums (see `verify_checksums`), that all
+    /// sections are within the buffer and that all strings can be decoded.
+    /// Accessors never panic on broken files but they silently return
+    /// `None` for data they cannot read, so this should be used for files
+    /// that might be truncated or corrupted.
+    pub fn validate(&self) -> Result<()> {
+        self.verify_checksums()?;
+
+        let head = &self.head;
+        if head.source_contents_codec().is_none() {
+            return bad_memdb("source contents use an unknown codec");
+        }
+
+        let index_ok = match head.version {
+            1 => self.index::<IndexItem>().is_some(),
+            _ => self.index::<LocIndexItem>().is_some(),
+        };
+        if !index_ok {
+            return bad_memdb("index section is out of bounds");
+        }
+
+        if head.version >= 2 {
+            let off = head.ids_start as usize;
+            let count = head.index_size as usize;
+            let ids_ok = match head.id_encoding() {
+                Some(IdEncoding::Packed) => self.get_records::<u32>(off, count).is_some(),
+                Some(IdEncoding::Wide) => self.get_records::<WideIds>(off, count).is_some(),
+                None => { return bad_memdb("id section has an unknown encoding"); }
+            };
+            if !ids_ok {
+                return bad_memdb("id section is out of bounds");
+            }
+        }
+
+        let names = match self.names() {
+            Some(x) => x,
+            None => { return bad_memdb("names section is out of bounds"); }
+        };
+        for offset in names.iter() {
+            if self.get_str_at(offset as usize).is_none() {
+                return bad_memdb("names section contains an invalid string");
+            }
+        }
+
+        let sources = match self.sources() {
+            Some(x) => x,
+            None => { return bad_memdb("sources section is out of bounds"); }
+        };
+        for offset in sources.iter() {
+            if self.get_str_at(offset as usize).is_none() {
+                return bad_memdb("sources section contains an invalid string");
+            }
+        }
+
+        let source_contents = match self.source_contents() {
+            Some(x) => x,
+            None => { return bad_memdb("source contents section is out of bounds"); }
+        };
+        for offset in source_contents.iter() {
+            if offset != !0 && self.get_bytes_at(offset as usize).is_none() {
+                return bad_memdb("source contents section contains invalid data");
+            }
+        }
+
+        let line_offsets = match self.line_offsets() {
+            Some(x) => x,
+            None => { return bad_memdb("line offsets section is out of bounds"); }
+        };
+        for offset in line_offsets.iter() {
+            if offset != !0 && self.get_bytes_at(offset as usize)
+                .is_none_or(|bytes| bytes.len() % 4 != 0)
+            {
+                return bad_memdb("line offsets section contains invalid data");
+            }
+        }
+
+        let reverse_index = match self.reverse_index() {
+            Some(x) => x,
+            None => { return bad_memdb("reverse index section is out of bounds"); }
+        };
+        if reverse_index.iter().any(|idx| idx >= head.index_size) {
+            return bad_memdb("reverse index section contains invalid token indexes");
+        }
+
+        if head.version >= 2 && head.debug_id != !0 &&
+            self.get_str_at(head.debug_id as usize).is_none()
+        {
+            return bad_memdb("debug id is not a valid string");
+        }
+
+        let function_names = match self.function_names() {
+            Some(x) => x,
+            None => { return bad_memdb("function names section is out of bounds"); }
+        };
+        for item in function_names.iter() {
+            if self.get_str_at(item.minified_name as usize).is_none() {
+                return bad_memdb("function names section contains an invalid string");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the checksums of the header and all sections.
+    ///
+    /// Version 1 files do not carry checksums and are accepted as they
+    /// are.
+    pub fn verify_checksums(&self) -> Result<()> {
+        let head = &self.head;
+        if head.version < 2 {
+            return Ok(());
+        }
+        if head.calculate_checksum() != head.header_checksum {
+            return Err(ErrorKind::ChecksumMismatch("header".into()).into());
+        }
+
+        let index_start = MapHead::size_for_version(head.version).unwrap_or(0);
+        let index_size = head.index_size as usize;
+        let ids_size = match head.id_encoding() {
+            Some(IdEncoding::Wide) => WideIds::SIZE,
+            _ => u32::SIZE,
+        };
+        let ids_start = head.ids_start as usize;
+        let ids_len = index_size.saturating_mul(ids_size);
+        let strings_start = ids_start.saturating_add(ids_len);
+        let sections = [
+            ("index", index_start,
+             index_size.saturating_mul(LocIndexItem::SIZE), head.index_checksum),
+            ("ids", ids_start, ids_len, head.ids_checksum),
+            ("strings", strings_start,
+             (head.names_start as usize).wrapping_sub(strings_start), head.strings_checksum),
+            ("names", head.names_start as usize,
+             head.names_count as usize * u32::SIZE, head.names_checksum),
+            ("sources", head.sources_start as usize,
+             head.sources_count as usize * u32::SIZE, head.sources_checksum),
+            ("function names", head.function_names_start as usize,
+             head.function_names_count as usize * FunctionNameItem::SIZE,
+             head.function_names_checksum),
+            ("source contents", head.source_contents_start as usize,
+             head.source_contents_count as usize * u32::SIZE,
+             head.source_contents_checksum),
+            ("line offsets", head.line_offsets_start as usize,
+             head.line_offsets_count as usize * u32::SIZE,
+             head.line_offsets_checksum),
+            ("reverse index", head.reverse_index_start as usize,
+             head.reverse_index_count as usize * u32::SIZE,
+             head.reverse_index_checksum),
+        ];
+
+        for &(name, start, len, checksum) in &sections {
+            let data = match self.get_data(start, len) {
+                Some(data) => data,
+                None => { return bad_memdb(&format!("{} section is out of bounds", name)); }
+            };
+            let mut hasher = Hasher::new();
+            hasher.update(data);
+            if hasher.finalize() != checksum {
+                return Err(ErrorKind::ChecksumMismatch(name.into()).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the header of the memdb file.
+    pub fn header(&self) -> &MapHead {
+        &self.head
+    }
+
+    pub fn get_name(&self, name_id: u32) -> Option<&str> {
+        self.names().and_then(|x| self.get_string(&x, name_id))
+    }
+
+    pub fn get_source(&self, src_id: u32) -> Option<&str> {
+        self.sources().and_then(|x| self.get_string(&x, src_id))
+    }
+
+    /// Returns the debug id that was embedded when the memdb was written.
+    pub fn get_debug_id(&self) -> Option<&str> {
+        let head = &self.head;
+        if head.version < 2 || head.debug_id == !0 {
+            return None;
+        }
+        self.get_str_at(head.debug_id as usize)
+    }
+
+    /// Decompresses the contents of a source.
+    ///
+    /// This does not go through the source cache, see
+    /// `get_cached_source_contents`.
+    pub fn get_source_contents(&self, src_id: u32) -> Option<String> {
+        let codec = self.head.source_contents_codec()?;
+        self.source_contents().and_then(|x| {
+            self.get_bytes(&x, src_id)
+        }).and_then(|bytes| codec.decompress(bytes).ok())
+    }
+
+    /// Returns the contents of a source from the source cache.
+    ///
+    /// The contents are only decompressed if they are not in the cache
+    /// yet.  This can be called from multiple threads.
+    pub fn get_cached_source_contents(&self, src_id: u32) -> Option<Arc<str>> {
+        if let Some(rv) = self.source_cache().get(src_id) {
+            return Some(rv);
+        }
+        let rv: Arc<str> = Arc::from(self.get_source_contents(src_id)?);
+        self.source_cache().insert(src_id, rv.clone());
+        Some(rv)
+    }
+
+    /// Returns the line offsets of a source.
+    ///
+    /// Version 1 files do not store them so they are computed from the
+    /// source contents.
+    pub fn get_line_offsets(&self, src_id: u32) -> Option<LineOffsets<'_>> {
+        if self.head.version < 2 {
+            return self.get_cached_source_contents(src_id)
+                .map(|contents| LineOffsets::from_source(&contents));
+        }
+        self.line_offsets()
+            .and_then(|x| x.get(src_id as usize))
+            .filter(|&offset| offset != !0)
+            .and_then(|offset| self.get_bytes_at(offset as usize))
+            .map(LineOffsets::Encoded)
+    }
+
+    /// Sets the maximum size in bytes of the source cache.
+    ///
+    /// Sources larger than the cache are never cached and a size of zero
+    /// disables the cache.
+    pub fn set_source_cache_size(&self, size: usize) {
+        self.source_cache().set_max_size(size);
+    }
+
+    fn source_cache(&self) -> MutexGuard<'_, SourceCache> {
+        // the cache is always consistent between calls so a panic in
+        // another thread does not matter.
+        self.source_cache.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn get_token_count(&self) -> u32 {
+        self.head.index_size
+    }
+
+    pub fn get_source_count(&self) -> u32 {
+        self.head.sources_count
+    }
+
+    pub fn get_token(&'a self, idx: u32) -> Option<Token<'a>> {
+        self.get_raw_token(idx as usize).map(|raw| {
+            Token {
+                db: self,
+                raw,
+            }
+        })
+    }
+
+    pub fn lookup_token(&'a self, line: u32, col: u32) -> Option<Token<'a>> {
+        self.lookup_token_idx(line, col).and_then(|idx| self.get_token(idx as u32))
+    }
+
+    /// Returns the index of the first token after a given line and column.
+    pub fn lookup_next_token_idx(&self, line: u32, col: u32) -> Option<u32> {
+        let next = self.lookup_token_idx(line, col).map_or(0, |idx| idx + 1);
+        if next < self.head.index_size as usize {
+            Some(next as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the indexes of the tokens that point to an original line.
+    ///
+    /// The tokens are ordered by original column.  This uses the reverse
+    /// index if the memdb has one and scans all tokens otherwise.
+    pub fn get_original_line_tokens(&self, src_id: u32, line: u32) -> Vec<u32> {
+        let key = |idx: u32| self.get_raw_token(idx as usize).map(|raw| {
+            (raw.src_id, raw.src_line, raw.src_col)
+        });
+
+        let reverse_index = match self.reverse_index() {
+            Some(items) if items.len() > 0 => items,
+            _ => {
+                let mut rv: Vec<_> = (0..self.head.index_size).filter_map(|idx| {
+                    key(idx).filter(|&(s, l, _)| s == src_id && l == line)
+                        .map(|(_, _, col)| (col, idx))
+                }).collect();
+                rv.sort();
+                return rv.into_iter().map(|(_, idx)| idx).collect();
+            }
+        };
+
+        let mut low = 0;
+        let mut high = reverse_index.len();
+        while low < high {
+            let mid = (low + high) / 2;
+            match reverse_index.get(mid).and_then(key) {
+                Some((s, l, _)) if (s, l) < (src_id, line) => low = mid + 1,
+                _ => high = mid,
+            }
+        }
+
+        (low..reverse_index.len())
+            .filter_map(|pos| reverse_index.get(pos))
+            .take_while(|&idx| {
+                key(idx).is_some_and(|(s, l, _)| s == src_id && l == line)
+            })
+            .collect()
     }
 
-    pub fn get_name(&self, name_id: u32) -> Option<&str> {
-        self.names().ok().and_then(|x| self.get_string(x, name_id))
+    /// Reconstructs a source map from the memdb.
+    ///
+    /// Names and source contents are only included if they were dumped
+    /// into the memdb.
+    pub fn to_sourcemap(&self) -> Result<SourceMap> {
+        self.build_sourcemap(true)
     }
 
-    pub fn get_source(&self, src_id: u32) -> Option<&str> {
-        self.sources().ok().and_then(|x| self.get_string(x, src_id))
-    }
+    fn build_sourcemap(&self, with_source_contents: bool) -> Result<SourceMap> {
+        let head = &self.head;
 
-    pub fn get_source_contents(&'a self, src_id: u32) -> Option<String> {
-        self.source_contents().ok().and_then(|x| {
-            self.get_bytes(x, src_id)
-        }).and_then(|bytes| {
-            let mut decompr = BrotliDecoder::new(bytes);
-            let mut contents = String::new();
-            decompr.read_to_string(&mut contents).ok();
-            Some(contents)
-        })
-    }
+        let mut tokens = Vec::with_capacity(head.index_size as usize);
+        for idx in 0..head.index_size as usize {
+            match self.get_raw_token(idx) {
+                Some(raw) => tokens.push(raw),
+                None => { return bad_memdb("index section is out of bounds"); }
+            }
+        }
 
-    pub fn get_token_count(&self) -> u32 {
-        self.header().map(|x| x.index_size).unwrap_or(0)
-    }
+        let mut names = Vec::with_capacity(head.names_count as usize);
+        for name_id in 0..head.names_count {
+            match self.get_name(name_id) {
+                Some(name) => names.push(name.to_string()),
+                None => { return bad_memdb("names section contains an invalid string"); }
+            }
+        }
 
-    pub fn get_source_count(&self) -> u32 {
-        self.header().map(|x| x.sources_count).unwrap_or(0)
-    }
+        let mut sources = Vec::with_capacity(head.sources_count as usize);
+        for src_id in 0..head.sources_count {
+            match self.get_source(src_id) {
+                Some(source) => sources.push(source.to_string()),
+                None => { return bad_memdb("sources section contains an invalid string"); }
+            }
+        }
 
-    pub fn get_token(&'a self, idx: u32) -> Option<Token<'a>> {
-        self.index().ok().and_then(|index| {
-            (&index.get(idx as usize)).map(|ii| {
-                Token {
-                    db: self,
-                    raw: RawToken {
-                        dst_line: ii.dst_line(),
-                        dst_col: ii.dst_col(),
-                        src_line: ii.src_line(),
-                        src_col: ii.src_col(),
-                        src_id: ii.src_id(),
-                        name_id: ii.name_id(),
-                    }
-                }
-            })
-        })
+        let source_contents = if with_source_contents && head.source_contents_count > 0 {
+            Some((0..head.sources_count).map(|src_id| {
+                self.get_source_contents(src_id)
+            }).collect())
+        } else {
+            None
+        };
+
+        Ok(SourceMap::new(None, tokens, names, sources, source_contents))
     }
 
-    pub fn lookup_token(&'a self, line: u32, col: u32) -> Option<Token<'a>> {
-        let index = match self.index() {
-            Ok(idx) => idx,
-            Err(_) => { return None; }
-        };
+    /// Resolves a minified function name to the original one.
+    ///
+    /// This only works if the minified source was provided when the memdb
+    /// was dumped, otherwise `None` is returned.  The result is the same as
+    /// `SourceMap::get_original_function_name` would return.
+    pub fn get_original_function_name(&self, line: u32, col: u32,
+                                      minified_name: &str) -> Option<&str> {
+        let items = self.function_names()?;
+        let token_idx = self.lookup_token_idx(line, col)? as u32;
+
+        // sourcemap only walks back a total of 1000 tokens including the
+        // one with the function keyword.
+        let min_idx = token_idx.saturating_sub(998);
         let mut low = 0;
-        let mut high = index.len();
-
+        let mut high = items.len();
         while low < high {
             let mid = (low + high) / 2;
-            let ii = &index[mid as usize];
-            if (line, col) < (ii.dst_line(), ii.dst_col()) {
-                high = mid;
-            } else {
+            if items.get(mid)?.token_idx <= token_idx {
                 low = mid + 1;
+            } else {
+                high = mid;
             }
         }
 
-        if low > 0 && low <= index.len() {
-            self.get_token(low as u32 - 1)
-        } else {
-            None
+        for idx in (0..low).rev() {
+            let item = items.get(idx)?;
+            if item.token_idx < min_idx {
+                break;
+            }
+            if self.get_str_at(item.minified_name as usize) == Some(minified_name) {
+                return self.get_name(item.name_id);
+            }
         }
+
+        None
     }
 
     #[inline(always)]
     pub fn buffer(&self) -> &[u8] {
-        match self.backing {
-            Backing::Buf(ref buf) => buf,
-            Backing::Mmap(ref mmap) => unsafe { mmap.as_slice() }
+        self.backing.buffer()
+    }
+
+    fn lookup_token_idx(&self, line: u32, col: u32) -> Option<usize> {
+        match self.head.version {
+            1 => lookup_index(&self.index::<IndexItem>()?, line, col),
+            _ => lookup_index(&self.index::<LocIndexItem>()?, line, col),
+        }
+    }
+
+    fn get_raw_token(&self, idx: usize) -> Option<RawToken> {
+        match self.head.version {
+            1 => self.index::<IndexItem>()?.get(idx).map(|ii| ii.raw_token()),
+            _ => {
+                let ii = self.index::<LocIndexItem>()?.get(idx)?;
+                let (src_id, name_id) = self.get_ids(idx)?;
+                Some(RawToken {
+                    dst_line: ii.dst_line,
+                    dst_col: ii.dst_col,
+                    src_line: ii.src_line,
+                    src_col: ii.src_col,
+                    src_id,
+                    name_id,
+                })
+            }
+        }
+    }
+
+    fn get_ids(&self, idx: usize) -> Option<(u32, u32)> {
+        let off = self.head.ids_start as usize;
+        let count = self.head.index_size as usize;
+        match self.head.id_encoding()? {
+            IdEncoding::Packed => {
+                let ids = self.get_records::<u32>(off, count)?.get(idx)?;
+                Some((unpack_src_id(ids), unpack_name_id(ids)))
+            }
+            IdEncoding::Wide => {
+                let ids = self.get_records::<WideIds>(off, count)?.get(idx)?;
+                Some((ids.src_id, ids.name_id))
+            }
         }
     }
 
-    fn get_data(&self, start: usize, len: usize) -> Result<&[u8]> {
+    fn get_data(&self, start: usize, len: usize) -> Option<&[u8]> {
         let buffer = self.buffer();
-        let end = start.wrapping_add(len);
-        if end < start || end > buffer.len() {
-            Err(ErrorKind::BadMemDb.into())
+        let end = start.checked_add(len)?;
+        if end > buffer.len() {
+            None
         } else {
-            Ok(&buffer[start..end])
+            Some(&buffer[start..end])
         }
     }
 
-    fn get_slice<T>(&self, offset: usize, count: usize) -> Result<&[T]> {
-        let size = mem::size_of::<T>();
-        Ok(unsafe {
-            slice::from_raw_parts(
-                mem::transmute(self.get_data(offset, count * size)?.as_ptr()),
-                count
-            )
-        })
+    fn get_records<T: Record>(&self, offset: usize, count: usize) -> Option<Records<'_, T>> {
+        let size = count.checked_mul(T::SIZE)?;
+        self.get_data(offset, size).map(Records::new)
     }
 
-    fn get_bytes(&self, coll: &[u32], idx: u32) -> Option<&[u8]> {
-        coll.get(idx as usize).and_then(|offset| {
-            let mut offset = *offset as usize;
-            let buffer = self.buffer();
-            let mut len = 0u64;
-            offset += varinteger::decode_with_offset(buffer, offset, &mut len) as usize;
-            Some(&buffer[offset..offset + len as usize])
-        })
+    fn get_bytes(&self, coll: &Records<'_, u32>, idx: u32) -> Option<&[u8]> {
+        coll.get(idx as usize).and_then(|offset| self.get_bytes_at(offset as usize))
     }
 
-    fn get_string(&self, coll: &[u32], idx: u32) -> Option<&str> {
-        self.get_bytes(coll, idx).and_then(|bytes| from_utf8(bytes).ok())
+    fn get_bytes_at(&self, offset: usize) -> Option<&[u8]> {
+        let (len, len_size) = decode_varint(self.buffer(), offset)?;
+        if len > usize::MAX as u64 {
+            return None;
+        }
+        self.get_data(offset + len_size, len as usize)
     }
 
-    #[inline(always)]
-    fn header(&self) -> Result<&MapHead> {
-        unsafe {
-            Ok(mem::transmute(self.get_data(0, mem::size_of::<MapHead>())?.as_ptr()))
-        }
+    fn get_str_at(&self, offset: usize) -> Option<&str> {
+        self.get_bytes_at(offset).and_then(|bytes| from_utf8(bytes).ok())
+    }
+
+    fn get_string(&self, coll: &Records<'_, u32>, idx: u32) -> Option<&str> {
+        self.get_bytes(coll, idx).and_then(|bytes| from_utf8(bytes).ok())
     }
 
     #[inline(always)]
-    fn index(&self) -> Result<&[IndexItem]> {
-        let head = self.header()?;
-        let off = mem::size_of::<MapHead>();
-        self.get_slice(off, head.index_size as usize)
+    fn index<T: IndexEntry + Record>(&self) -> Option<Records<'_, T>> {
+        let head = &self.head;
+        let off = MapHead::size_for_version(head.version).unwrap_or(0);
+        self.get_records(off, head.index_size as usize)
     }
 
     #[inline(always)]
-    fn names(&self) -> Result<&[u32]> {
-        let head = self.header()?;
+    fn names(&self) -> Option<Records<'_, u32>> {
+        let head = &self.head;
         let off = head.names_start as usize;
-        self.get_slice(off, head.names_count as usize)
+        self.get_records(off, head.names_count as usize)
     }
 
     #[inline(always)]
-    fn sources(&self) -> Result<&[u32]> {
-        let head = self.header()?;
+    fn sources(&self) -> Option<Records<'_, u32>> {
+        let head = &self.head;
         let off = head.sources_start as usize;
-        self.get_slice(off, head.sources_count as usize)
+        self.get_records(off, head.sources_count as usize)
     }
 
     #[inline(always)]
-    fn source_contents(&self) -> Result<&[u32]> {
-        let head = self.header()?;
+    fn source_contents(&self) -> Option<Records<'_, u32>> {
+        let head = &self.head;
         let off = head.source_contents_start as usize;
-        self.get_slice(off, head.source_contents_count as usize)
+        self.get_records(off, head.source_contents_count as usize)
+    }
+
+    #[inline(always)]
+    fn reverse_index(&self) -> Option<Records<'_, u32>> {
+        let head = &self.head;
+        let off = head.reverse_index_start as usize;
+        self.get_records(off, head.reverse_index_count as usize)
+    }
+
+    #[inline(always)]
+    fn line_offsets(&self) -> Option<Records<'_, u32>> {
+        let head = &self.head;
+        let off = head.line_offsets_start as usize;
+        self.get_records(off, head.line_offsets_count as usize)
+    }
+
+    #[inline(always)]
+    fn function_names(&self) -> Option<Records<'_, FunctionNameItem>> {
+        let head = &self.head;
+        let off = head.function_names_start as usize;
+        self.get_records(off, head.function_names_count as usize)
     }
 }
 
@@ -405,15 +1355,47 @@ impl<'a> fmt::Display for Token<'a> {
     }
 }
 
-fn write_obj<T, W: Write>(w: &mut W, x: &T) -> io::Result<u32> {
-    unsafe {
-        let bytes : *const u8 = mem::transmute(x);
-        let size = mem::size_of_val(x);
-        w.write_all(slice::from_raw_parts(bytes, size))?;
-        Ok(size as u32)
+/// A writer that checksums everything written to it section by section.
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> ChecksumWriter<W> {
+        ChecksumWriter {
+            inner,
+            hasher: Hasher::new(),
+        }
+    }
+
+    /// Returns the checksum of the data written since the last call.
+    fn finish_section(&mut self) -> u32 {
+        mem::replace(&mut self.hasher, Hasher::new()).finalize()
+    }
+
+    fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
 
+fn write_obj<T: Record, W: Write>(w: &mut W, x: &T) -> io::Result<u32> {
+    x.encode(w)?;
+    Ok(T::SIZE as u32)
+}
+
 fn write_str<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<u32> {
     let mut buf = [0u8; 8];
     let off = varinteger::encode(bytes.len() as u64, &mut buf);
@@ -422,46 +1404,490 @@ fn write_str<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<u32> {
     Ok(bytes.len() as u32 + off as u32)
 }
 
-fn write_slice<T, W: Write>(w: &mut W, x: &[T]) -> io::Result<u32> {
-    unsafe {
-        let bytes : *const u8 = mem::transmute(x.as_ptr());
-        let size = mem::size_of::<T>() * x.len();
-        w.write_all(slice::from_raw_parts(bytes, size))?;
-        Ok(size as u32)
+fn write_slice<T: Record, W: Write>(w: &mut W, x: &[T]) -> io::Result<u32> {
+    for item in x {
+        item.encode(w)?;
+    }
+    Ok((T::SIZE * x.len()) as u32)
+}
+
+/// The data a memdb is written from.
+trait MapData {
+    fn get_index_size(&self) -> u32;
+    fn get_name_count(&self) -> u32;
+    fn get_name(&self, idx: u32) -> Option<&str>;
+    fn get_source_count(&self) -> u32;
+    fn get_source(&self, idx: u32) -> Option<&str>;
+    fn get_source_contents(&self, idx: u32) -> Option<&str>;
+    /// Returns the tokens in minified order.
+    fn raw_tokens(&self) -> Box<dyn Iterator<Item = RawToken> + '_>;
+}
+
+impl MapData for SourceMap {
+    fn get_index_size(&self) -> u32 {
+        SourceMap::get_index_size(self) as u32
+    }
+
+    fn get_name_count(&self) -> u32 {
+        SourceMap::get_name_count(self)
+    }
+
+    fn get_name(&self, idx: u32) -> Option<&str> {
+        SourceMap::get_name(self, idx)
+    }
+
+    fn get_source_count(&self) -> u32 {
+        SourceMap::get_source_count(self)
+    }
+
+    fn get_source(&self, idx: u32) -> Option<&str> {
+        SourceMap::get_source(self, idx)
+    }
+
+    fn get_source_contents(&self, idx: u32) -> Option<&str> {
+        SourceMap::get_source_contents(self, idx)
+    }
+
+    fn raw_tokens(&self) -> Box<dyn Iterator<Item = RawToken> + '_> {
+        Box::new(self.index_iter().map(move |(_, _, token_id)| {
+            self.get_token(token_id).unwrap().get_raw_token()
+        }))
+    }
+}
+
+/// A section of a `MergedIndex`.
+struct MergedSection<'a> {
+    sm: &'a SourceMap,
+    offset: (u32, u32),
+    /// Maps the source and name ids of the section to the merged ones.
+    src_ids: Vec<u32>,
+    name_ids: Vec<u32>,
+}
+
+/// The sections of an index merged into a single map without flattening.
+///
+/// Sources and names are deduplicated in the order they are first used
+/// and like `SourceMapIndex::flatten` the column offset of a section is
+/// applied to all of its lines.  Indexes with overlapping sections are
+/// rejected (see `verify_index_sections`).
+struct MergedIndex<'a> {
+    sections: Vec<MergedSection<'a>>,
+    names: Vec<&'a str>,
+    sources: Vec<&'a str>,
+    source_contents: Vec<Option<&'a str>>,
+    index_size: u32,
+}
+
+impl<'a> MergedSection<'a> {
+
+    /// Moves a token of the section to its minified location in the index.
+    fn translate(&self, raw: &RawToken) -> Option<(u32, u32)> {
+        let (off_line, off_col) = self.offset;
+        let dst_line = raw.dst_line.checked_add(off_line)?;
+        let dst_col = raw.dst_col.checked_add(off_col)?;
+        Some((dst_line, dst_col))
+    }
+
+    fn raw_tokens(&self) -> impl Iterator<Item = (RawToken, (u32, u32))> + '_ {
+        self.sm.index_iter().filter_map(move |(_, _, token_id)| {
+            let raw = self.sm.get_token(token_id)?.get_raw_token();
+            self.translate(&raw).map(|dst| (raw, dst))
+        })
+    }
+}
+
+/// Verifies that the sections of an index are sorted and do not overlap.
+///
+/// Flattening interleaves the tokens of a section that extends past the
+/// offset of the next section with the tokens of that section which
+/// lookups and dumps without flattening cannot reproduce.  Such indexes
+/// fail with `CannotFlatten`.  Sections without a loaded map are only
+/// checked for their offset.
+pub fn verify_index_sections(smi: &SourceMapIndex) -> Result<()> {
+    let mut sections = smi.sections().enumerate().peekable();
+    while let Some((idx, section)) = sections.next() {
+        let next_offset = match sections.peek() {
+            Some(&(_, next)) => next.get_offset(),
+            None => break,
+        };
+        let (off_line, off_col) = section.get_offset();
+        let last = section.get_sourcemap()
+            .and_then(|sm| sm.index_iter().last())
+            .map(|(dst_line, dst_col, _)| {
+                (dst_line.checked_add(off_line), dst_col.checked_add(off_col))
+            });
+        let overlaps = match last {
+            Some((Some(dst_line), Some(dst_col))) => (dst_line, dst_col) >= next_offset,
+            Some(_) => { return Err(ErrorKind::LocationOverflow.into()); }
+            None => (off_line, off_col) > next_offset,
+        };
+        if overlaps {
+            return Err(SourceMapError::CannotFlatten(format!(
+                "section {} overlaps the next section", idx)).into());
+        }
+    }
+    Ok(())
+}
+
+impl<'a> MergedIndex<'a> {
+    fn new(smi: &'a SourceMapIndex) -> Result<MergedIndex<'a>> {
+        verify_index_sections(smi)?;
+        let mut rv = MergedIndex {
+            sections: vec![],
+            names: vec![],
+            sources: vec![],
+            source_contents: vec![],
+            index_size: 0,
+        };
+        let mut name_map = HashMap::new();
+        let mut source_map = HashMap::new();
+
+        for section in smi.sections() {
+            let sm = match section.get_sourcemap() {
+                Some(sm) => sm,
+                None => {
+                    return Err(SourceMapError::CannotFlatten(format!(
+                        "Section has an unresolved sourcemap: {}",
+                        section.get_url().unwrap_or("<unknown url>"))).into());
+                }
+            };
+            let mut merged = MergedSection {
+                sm,
+                offset: section.get_offset(),
+                src_ids: vec![],
+                name_ids: vec![],
+            };
+
+            let mut src_ids = vec![!0; sm.get_source_count() as usize];
+            let mut name_ids = vec![!0; sm.get_name_count() as usize];
+            let mut index_size = 0;
+            for (raw, _) in merged.raw_tokens() {
+                index_size += 1;
+                if let Some(source) = sm.get_source(raw.src_id) {
+                    let src_id = *source_map.entry(source).or_insert_with(|| {
+                        rv.sources.push(source);
+                        rv.source_contents.push(None);
+                        rv.sources.len() as u32 - 1
+                    });
+                    let contents = &mut rv.source_contents[src_id as usize];
+                    if contents.is_none() {
+                        *contents = sm.get_source_contents(raw.src_id);
+                    }
+                    src_ids[raw.src_id as usize] = src_id;
+                }
+                if let Some(name) = sm.get_name(raw.name_id) {
+                    name_ids[raw.name_id as usize] =
+                        *name_map.entry(name).or_insert_with(|| {
+                            rv.names.push(name);
+                            rv.names.len() as u32 - 1
+                        });
+                }
+            }
+
+            merged.src_ids = src_ids;
+            merged.name_ids = name_ids;
+            rv.index_size = rv.index_size.checked_add(index_size)
+                .ok_or(ErrorKind::LocationOverflow)?;
+            rv.sections.push(merged);
+        }
+
+        Ok(rv)
+    }
+}
+
+impl<'a> MapData for MergedIndex<'a> {
+    fn get_index_size(&self) -> u32 {
+        self.index_size
+    }
+
+    fn get_name_count(&self) -> u32 {
+        self.names.len() as u32
+    }
+
+    fn get_name(&self, idx: u32) -> Option<&str> {
+        self.names.get(idx as usize).cloned()
+    }
+
+    fn get_source_count(&self) -> u32 {
+        self.sources.len() as u32
+    }
+
+    fn get_source(&self, idx: u32) -> Option<&str> {
+        self.sources.get(idx as usize).cloned()
+    }
+
+    fn get_source_contents(&self, idx: u32) -> Option<&str> {
+        self.source_contents.get(idx as usize).cloned().and_then(|x| x)
+    }
+
+    fn raw_tokens(&self) -> Box<dyn Iterator<Item = RawToken> + '_> {
+        Box::new(self.sections.iter().flat_map(|section| {
+            section.raw_tokens().map(move |(raw, (dst_line, dst_col))| RawToken {
+                dst_line,
+                dst_col,
+                src_line: raw.src_line,
+                src_col: raw.src_col,
+                src_id: section.src_ids.get(raw.src_id as usize).cloned().unwrap_or(!0),
+                name_id: section.name_ids.get(raw.name_id as usize).cloned().unwrap_or(!0),
+            })
+        }))
+    }
+}
+
+/// A function name that is embedded into a memdb.
+struct FunctionName<'a> {
+    token_idx: u32,
+    minified_name: &'a str,
+    name_id: u32,
+}
+
+/// Data that is computed once before a memdb is written.
+struct Prepared<'a> {
+    /// The memdb that is dumped again if any.
+    db: Option<&'a MemDb<'a>>,
+    /// The compressed source contents.  These are only kept in memory for
+    /// streams which are written twice, otherwise every source is
+    /// compressed when it is written.
+    source_contents: Option<Vec<Option<Cow<'a, [u8]>>>>,
+    line_offsets: Vec<Option<Cow<'a, [u8]>>>,
+    function_names: Vec<FunctionName<'a>>,
+}
+
+fn find_function_names<'a>(sm: &dyn MapData, minified_source: &'a str)
+    -> Vec<FunctionName<'a>>
+{
+    let mut rv = vec![];
+    let mut cursor = SourceCursor::new(minified_source);
+    let mut prev_ident = None;
+    for (token_idx, raw) in sm.raw_tokens().enumerate() {
+        let ident = cursor.get_token(raw.dst_line, raw.dst_col);
+        if let (Some("function"), Some(ident)) = (prev_ident, ident) {
+            rv.push(FunctionName {
+                token_idx: token_idx as u32,
+                minified_name: ident,
+                name_id: raw.name_id,
+            });
+        }
+        prev_ident = ident;
+    }
+    rv
+}
+
+impl<'a> Prepared<'a> {
+
+    /// Prepares dumping a source map.
+    fn from_sourcemap(sm: &dyn MapData, opts: &DumpOptions<'a>) -> Result<Prepared<'a>> {
+        opts.source_contents_codec.validate()?;
+        let mut line_offsets = vec![];
+        if opts.with_source_contents {
+            for source_id in 0..sm.get_source_count() {
+                let contents = sm.get_source_contents(source_id);
+                line_offsets.push(contents.map(|contents| {
+                    Cow::Owned(LineOffsets::from_source(contents).encode())
+                }));
+            }
+        }
+
+        Ok(Prepared {
+            db: None,
+            source_contents: None,
+            line_offsets,
+            function_names: match opts.minified_source {
+                Some(minified_source) => find_function_names(sm, minified_source),
+                None => vec![],
+            },
+        })
+    }
+
+    /// Prepares dumping a memdb.
+    ///
+    /// `sm` needs to be reconstructed from the memdb.  The function names
+    /// are copied over unless a new minified source is provided.
+    fn from_memdb(db: &'a MemDb<'a>, sm: &SourceMap, opts: &DumpOptions<'a>)
+        -> Result<Prepared<'a>>
+    {
+        opts.source_contents_codec.validate()?;
+        let mut line_offsets = vec![];
+        if opts.with_source_contents {
+            for src_id in 0..db.get_source_count() {
+                line_offsets.push(match db.get_line_offsets(src_id) {
+                    Some(LineOffsets::Encoded(bytes)) => Some(Cow::Borrowed(bytes)),
+                    Some(offsets) => Some(Cow::Owned(offsets.encode())),
+                    None => None,
+                });
+            }
+        }
+
+        let function_names = match opts.minified_source {
+            Some(minified_source) => find_function_names(sm, minified_source),
+            None => {
+                db.function_names().map(|items| {
+                    items.iter().filter_map(|item| {
+                        db.get_str_at(item.minified_name as usize).map(|minified_name| {
+                            FunctionName {
+                                token_idx: item.token_idx,
+                                minified_name,
+                                name_id: item.name_id,
+                            }
+                        })
+                    }).collect()
+                }).unwrap_or_default()
+            }
+        };
+
+        Ok(Prepared {
+            db: Some(db),
+            source_contents: None,
+            line_offsets,
+            function_names,
+        })
+    }
+
+    /// Compresses the contents of a source.
+    ///
+    /// The compressed contents of a memdb are copied over as they are if
+    /// the codec does not change.
+    fn compress_source_contents(&self, sm: &dyn MapData, opts: &DumpOptions, src_id: u32)
+        -> Result<Option<Cow<'a, [u8]>>>
+    {
+        let codec = opts.source_contents_codec;
+        let db = match self.db {
+            Some(db) => db,
+            None => {
+                return match sm.get_source_contents(src_id) {
+                    Some(contents) => Ok(Some(Cow::Owned(codec.compress(contents.as_bytes())?))),
+                    None => Ok(None),
+                };
+            }
+        };
+        let bytes = db.source_contents()
+            .and_then(|coll| coll.get(src_id as usize))
+            .filter(|&offset| offset != !0)
+            .and_then(|offset| db.get_bytes_at(offset as usize));
+        Ok(match bytes {
+            Some(bytes) if db.head.source_contents_codec() == Some(codec) => {
+                Some(Cow::Borrowed(bytes))
+            }
+            Some(_) => match db.get_source_contents(src_id) {
+                Some(contents) => Some(Cow::Owned(codec.compress(contents.as_bytes())?)),
+                None => None,
+            },
+            None => None,
+        })
+    }
+
+    /// Compresses all source contents upfront so that writing the memdb
+    /// twice does not compress them twice.
+    fn compress_all_source_contents(&mut self, sm: &dyn MapData, opts: &DumpOptions)
+        -> Result<()>
+    {
+        if opts.with_source_contents && self.source_contents.is_none() {
+            let source_contents = (0..sm.get_source_count())
+                .map(|src_id| self.compress_source_contents(sm, opts, src_id))
+                .collect::<Result<Vec<_>>>()?;
+            self.source_contents = Some(source_contents);
+        }
+        Ok(())
+    }
+
+    /// Returns the compressed contents of a source.
+    fn get_source_contents(&self, sm: &dyn MapData, opts: &DumpOptions, src_id: u32)
+        -> Result<Option<Cow<'_, [u8]>>>
+    {
+        match self.source_contents {
+            Some(ref source_contents) => {
+                Ok(source_contents.get(src_id as usize)
+                   .and_then(|x| x.as_ref())
+                   .map(|x| Cow::Borrowed(&x[..])))
+            }
+            None => self.compress_source_contents(sm, opts, src_id),
+        }
     }
 }
 
-fn sourcemap_to_memdb_common<W: Write>(sm: &SourceMap, mut w: W, opts: DumpOptions)
+/// Writes the memdb.  If `final_head` is given it is written instead of
+/// the preliminary header so that the output does not need patching.
+fn sourcemap_to_memdb_common<W: Write>(sm: &dyn MapData, w: W, opts: &DumpOptions,
+                                       prepared: &Prepared,
+                                       final_head: Option<&MapHead>)
     -> Result<(W, MapHead)>
 {
+    let mut w = ChecksumWriter::new(w);
+
+    let names_count = if opts.with_names { sm.get_name_count() } else { 0 };
+    let id_encoding = if sm.get_source_count() < 0x3fff && names_count < 0x3ffff {
+        IdEncoding::Packed
+    } else {
+        IdEncoding::Wide
+    };
+
     let mut head = MapHead {
-        version: 1,
-        index_size: sm.get_index_size() as u32,
+        version: MEMDB_VERSION,
+        index_size: sm.get_index_size(),
         names_start: 0,
-        names_count: if opts.with_names { sm.get_name_count() } else { 0 },
+        names_count,
         sources_start: 0,
         sources_count: sm.get_source_count(),
         source_contents_start: 0,
         source_contents_count: 0,
+        ids_start: 0,
+        ids_encoding: match id_encoding {
+            IdEncoding::Packed => 0,
+            IdEncoding::Wide => 1,
+        },
+        function_names_start: 0,
+        function_names_count: 0,
+        header_checksum: 0,
+        index_checksum: 0,
+        ids_checksum: 0,
+        strings_checksum: 0,
+        names_checksum: 0,
+        sources_checksum: 0,
+        function_names_checksum: 0,
+        source_contents_checksum: 0,
+        source_contents_codec: opts.source_contents_codec.to_raw().0,
+        source_contents_level: opts.source_contents_codec.to_raw().1,
+        line_offsets_start: 0,
+        line_offsets_count: 0,
+        line_offsets_checksum: 0,
+        reverse_index_start: 0,
+        reverse_index_count: 0,
+        reverse_index_checksum: 0,
+        debug_id: !0,
     };
 
     // this will later be the information where to skip to for the TOCs
-    let mut idx = write_obj(&mut w, &head)?;
+    let mut idx = write_obj(&mut w, final_head.unwrap_or(&head))?;
+    w.finish_section();
 
     // write the index
-    for (line, col, token_id) in sm.index_iter() {
-        let token = sm.get_token(token_id).unwrap();
-        let raw = token.get_raw_token();
-        assert!(line == raw.dst_line);
-        assert!(col == raw.dst_col);
-        idx += write_obj(&mut w, &IndexItem::new(&raw)?)?;
+    for raw in sm.raw_tokens() {
+        idx += write_obj(&mut w, &LocIndexItem::new(&raw))?;
+    }
+
+    head.index_checksum = w.finish_section();
+
+    // write the id column
+    head.ids_start = idx;
+    for raw in sm.raw_tokens() {
+        let name_id = if opts.with_names { raw.name_id } else { !0 };
+        idx += match id_encoding {
+            IdEncoding::Packed => write_obj(&mut w, &pack_ids(raw.src_id, name_id)?)?,
+            IdEncoding::Wide => write_obj(&mut w, &WideIds {
+                src_id: raw.src_id,
+                name_id,
+            })?,
+        };
     }
 
+    head.ids_checksum = w.finish_section();
+
     // write names
     let names = {
         if opts.with_names {
             let mut names = Vec::with_capacity(sm.get_name_count() as usize);
-            for name in sm.names() {
+            for name_id in 0..sm.get_name_count() {
+                let name = sm.get_name(name_id).unwrap();
                 names.push(idx);
                 idx += write_str(&mut w, name.as_bytes())?;
             }
@@ -478,6 +1904,7 @@ fn sourcemap_to_memdb_common<W: Write>(sm: &SourceMap, mut w: W, opts: DumpOptio
     } else {
         vec![]
     };
+    let mut line_offsets = Vec::with_capacity(source_contents.capacity());
     let mut have_sources = false;
     for source_id in 0..sm.get_source_count() {
         let source = sm.get_source(source_id).unwrap();
@@ -485,53 +1912,112 @@ fn sourcemap_to_memdb_common<W: Write>(sm: &SourceMap, mut w: W, opts: DumpOptio
         idx += write_str(&mut w, source.as_bytes())?;
 
         if opts.with_source_contents {
-            if let Some(contents) = sm.get_source_contents(source_id) {
+            if let Some(compressed) = prepared.get_source_contents(sm, opts, source_id)? {
                 have_sources = true;
                 source_contents.push(idx);
-                let mut compressed = vec![];
-                let mut compr = BrotliEncoder::new(contents.as_bytes(), 4);
-                compr.read_to_end(&mut compressed)?;
                 idx += write_str(&mut w, &compressed[..])?;
             } else {
                 source_contents.push(!0);
             }
+            if let Some(Some(offsets)) = prepared.line_offsets.get(source_id as usize) {
+                line_offsets.push(idx);
+                idx += write_str(&mut w, &offsets[..])?;
+            } else {
+                line_offsets.push(!0);
+            }
+        }
+    }
+
+    // write the minified function names.  Without names there is
+    // nothing they could be resolved to.
+    let mut function_names = vec![];
+    if opts.with_names {
+        let mut minified_names = HashMap::new();
+        for item in &prepared.function_names {
+            let minified_name = match minified_names.get(item.minified_name) {
+                Some(&offset) => offset,
+                None => {
+                    let offset = idx;
+                    idx += write_str(&mut w, item.minified_name.as_bytes())?;
+                    minified_names.insert(item.minified_name, offset);
+                    offset
+                }
+            };
+            function_names.push(FunctionNameItem {
+                token_idx: item.token_idx,
+                minified_name,
+                name_id: item.name_id,
+            });
         }
     }
 
+    if let Some(debug_id) = opts.debug_id {
+        head.debug_id = idx;
+        idx += write_str(&mut w, debug_id.as_bytes())?;
+    }
+
+    head.strings_checksum = w.finish_section();
+
     // write indexes
     head.names_start = idx;
     idx += write_slice(&mut w, &names)?;
+    head.names_checksum = w.finish_section();
     head.sources_start = idx;
     idx += write_slice(&mut w, &sources)?;
+    head.sources_checksum = w.finish_section();
+    head.function_names_start = idx;
+    head.function_names_count = function_names.len() as u32;
+    idx += write_slice(&mut w, &function_names)?;
+    head.function_names_checksum = w.finish_section();
 
     if have_sources {
         head.source_contents_start = idx;
         head.source_contents_count = source_contents.len() as u32;
-        write_slice(&mut w, &source_contents)?;
+        idx += write_slice(&mut w, &source_contents)?;
+        head.source_contents_checksum = w.finish_section();
+        head.line_offsets_start = idx;
+        head.line_offsets_count = line_offsets.len() as u32;
+        idx += write_slice(&mut w, &line_offsets)?;
+        head.line_offsets_checksum = w.finish_section();
+    }
+
+    if opts.with_reverse_index {
+        let mut reverse_index = vec![];
+        for (token_idx, raw) in sm.raw_tokens().enumerate() {
+            if raw.src_id != !0 {
+                reverse_index.push((raw.src_id, raw.src_line, raw.src_col, token_idx as u32));
+            }
+        }
+        reverse_index.sort();
+        let reverse_index: Vec<u32> = reverse_index.into_iter().map(|x| x.3).collect();
+        head.reverse_index_start = idx;
+        head.reverse_index_count = reverse_index.len() as u32;
+        write_slice(&mut w, &reverse_index)?;
+        head.reverse_index_checksum = w.finish_section();
     }
 
-    Ok((w, head))
+    head.header_checksum = head.calculate_checksum();
+
+    Ok((w.into_inner(), head))
 }
 
-/// Serializes a map into a vec
-pub fn sourcemap_to_memdb_vec(sm: &SourceMap, opts: DumpOptions) -> Vec<u8> {
+fn write_memdb_vec(sm: &dyn MapData, opts: &DumpOptions, prepared: &Prepared)
+    -> Result<Vec<u8>>
+{
     let mut rv = vec![];
-    let (_, head) = sourcemap_to_memdb_common(sm, &mut rv, opts).unwrap();
+    let (_, head) = sourcemap_to_memdb_common(sm, &mut rv, opts, prepared, None)?;
 
-    unsafe {
-        let byte_head : *const u8 = mem::transmute(&head);
-        copy_nonoverlapping(byte_head, rv.as_mut_ptr(),
-                            mem::size_of_val(&head));
-    }
+    let mut head_bytes = Vec::with_capacity(MapHead::SIZE);
+    write_obj(&mut head_bytes, &head)?;
+    rv[..head_bytes.len()].copy_from_slice(&head_bytes);
 
-    rv
+    Ok(rv)
 }
 
-/// Serializes a map into a given writer
-pub fn sourcemap_to_memdb<W: Write+Seek>(sm: &SourceMap, w: W, opts: DumpOptions)
+fn write_memdb<W: Write+Seek>(sm: &dyn MapData, w: W, opts: &DumpOptions, prepared: &Prepared)
     -> Result<()>
 {
-    let (mut w, head) = sourcemap_to_memdb_common(sm, w, opts)?;
+    let (mut w, head) = sourcemap_to_memdb_common(sm, w, opts, prepared, None)?;
 
     // write offsets
     w.seek(SeekFrom::Start(0))?;
@@ -539,3 +2025,95 @@ pub fn sourcemap_to_memdb<W: Write+Seek>(sm: &SourceMap, w: W, opts: DumpOptions
 
     Ok(())
 }
+
+fn write_memdb_stream<W: Write>(sm: &dyn MapData, w: W, opts: &DumpOptions,
+                                mut prepared: Prepared)
+    -> Result<()>
+{
+    prepared.compress_all_source_contents(sm, opts)?;
+    let prepared = &prepared;
+    let (_, head) = sourcemap_to_memdb_common(sm, io::sink(), opts, prepared, None)?;
+    let (mut w, _) = sourcemap_to_memdb_common(sm, w, opts, prepared, Some(&head))?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Serializes a map into a vec
+pub fn sourcemap_to_memdb_vec(sm: &SourceMap, opts: DumpOptions) -> Result<Vec<u8>> {
+    let prepared = Prepared::from_sourcemap(sm, &opts)?;
+    write_memdb_vec(sm, &opts, &prepared)
+}
+
+/// Serializes a map into a given writer
+pub fn sourcemap_to_memdb<W: Write+Seek>(sm: &SourceMap, w: W, opts: DumpOptions)
+    -> Result<()>
+{
+    let prepared = Prepared::from_sourcemap(sm, &opts)?;
+    write_memdb(sm, w, &opts, &prepared)
+}
+
+/// Serializes a map into a writer that does not need to be seekable.
+///
+/// As the header cannot be patched afterwards the offsets and checksums
+/// are calculated in a first pass that does not write anything.  Only
+/// the compressed source contents are kept in memory for this.
+pub fn sourcemap_to_memdb_stream<W: Write>(sm: &SourceMap, w: W, opts: DumpOptions)
+    -> Result<()>
+{
+    let prepared = Prepared::from_sourcemap(sm, &opts)?;
+    write_memdb_stream(sm, w, &opts, prepared)
+}
+
+/// Serializes an index into a vec without flattening it first.
+///
+/// All sections need to have their maps loaded.
+pub fn index_to_memdb_vec(smi: &SourceMapIndex, opts: DumpOptions) -> Result<Vec<u8>> {
+    let merged = MergedIndex::new(smi)?;
+    let prepared = Prepared::from_sourcemap(&merged, &opts)?;
+    write_memdb_vec(&merged, &opts, &prepared)
+}
+
+/// Serializes an index into a given writer without flattening it first.
+pub fn index_to_memdb<W: Write+Seek>(smi: &SourceMapIndex, w: W, opts: DumpOptions)
+    -> Result<()>
+{
+    let merged = MergedIndex::new(smi)?;
+    let prepared = Prepared::from_sourcemap(&merged, &opts)?;
+    write_memdb(&merged, w, &opts, &prepared)
+}
+
+/// Serializes an index into a writer that does not need to be seekable
+/// without flattening it first.
+pub fn index_to_memdb_stream<W: Write>(smi: &SourceMapIndex, w: W, opts: DumpOptions)
+    -> Result<()>
+{
+    let merged = MergedIndex::new(smi)?;
+    let prepared = Prepared::from_sourcemap(&merged, &opts)?;
+    write_memdb_stream(&merged, w, &opts, prepared)
+}
+
+/// Serializes a memdb into a vec with different options.
+///
+/// The result is always written in the current format version so this
+/// can also be used to upgrade older files.  Names and source contents
+/// can only be kept if the original memdb has them.
+pub fn memdb_to_memdb_vec(db: &MemDb, opts: DumpOptions) -> Result<Vec<u8>> {
+    let sm = db.build_sourcemap(false)?;
+    let prepared = Prepared::from_memdb(db, &sm, &opts)?;
+    write_memdb_vec(&sm, &opts, &prepared)
+}
+
+/// Serializes a memdb into a given writer with different options.
+pub fn memdb_to_memdb<W: Write+Seek>(db: &MemDb, w: W, opts: DumpOptions) -> Result<()> {
+    let sm = db.build_sourcemap(false)?;
+    let prepared = Prepared::from_memdb(db, &sm, &opts)?;
+    write_memdb(&sm, w, &opts, &prepared)
+}
+
+/// Serializes a memdb into a writer that does not need to be seekable
+/// with different options.
+pub fn memdb_to_memdb_stream<W: Write>(db: &MemDb, w: W, opts: DumpOptions) -> Result<()> {
+    let sm = db.build_sourcemap(false)?;
+    let prepared = Prepared::from_memdb(db, &sm, &opts)?;
+    write_memdb_stream(&sm, w, &opts, prepared)
+}
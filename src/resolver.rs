@@ -0,0 +1,67 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use errors::Result;
+
+
+/// Supplies the source maps of index sections that reference a `url`
+/// instead of embedding a `map`.
+pub trait SectionResolver {
+    /// Returns the source map bytes for the section URL or `None` if the
+    /// URL cannot be resolved.
+    fn resolve(&mut self, url: &str) -> Result<Option<Vec<u8>>>;
+}
+
+impl<F: FnMut(&str) -> Result<Option<Vec<u8>>>> SectionResolver for F {
+    fn resolve(&mut self, url: &str) -> Result<Option<Vec<u8>>> {
+        self(url)
+    }
+}
+
+/// Resolves section URLs to files relative to a local directory.
+///
+/// Only relative paths are resolved.  URLs with a scheme, absolute paths
+/// and paths that would leave the directory are treated as unresolvable.
+pub struct DirectoryResolver {
+    base: PathBuf,
+}
+
+impl DirectoryResolver {
+    pub fn new<P: AsRef<Path>>(base: P) -> DirectoryResolver {
+        DirectoryResolver {
+            base: base.as_ref().to_path_buf(),
+        }
+    }
+
+    fn get_path(&self, url: &str) -> Option<PathBuf> {
+        if url.contains(':') {
+            return None;
+        }
+        // query strings and fragments do not point to other files
+        let url = url.split(['?', '#']).next().unwrap_or("");
+        let mut path = self.base.clone();
+        for component in Path::new(url).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => { return None; }
+            }
+        }
+        Some(path)
+    }
+}
+
+impl SectionResolver for DirectoryResolver {
+    fn resolve(&mut self, url: &str) -> Result<Option<Vec<u8>>> {
+        let path = match self.get_path(url) {
+            Some(path) => path,
+            None => { return Ok(None); }
+        };
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
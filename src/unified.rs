@@ -1,15 +1,29 @@
-use std::io::Read;
+use std::fs::File;
+use std::io::{self, Read, Write, BufWriter};
 use std::path::Path;
 use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+use std::collections::HashMap;
 
-use sourcemap::{SourceMap, SourceMapIndex, decode_slice, DecodedMap};
+use serde_json;
+use sourcemap::{SourceMap, SourceMapIndex, SourceMapSection, SourceMapBuilder, RawToken,
+                decode_slice, DecodedMap, Error as SourceMapError};
 
-use memdb::{MemDb, sourcemap_to_memdb_vec, DumpOptions};
-use errors::{Result, ErrorKind};
+use memdb::{MemDb, sourcemap_to_memdb, sourcemap_to_memdb_vec, sourcemap_to_memdb_stream,
+            memdb_to_memdb, memdb_to_memdb_vec, memdb_to_memdb_stream, index_to_memdb,
+            index_to_memdb_vec, index_to_memdb_stream, verify_index_sections, DumpOptions};
+use errors::{ErrorKind, Result};
+use dataurl::{decode_data_url, decode_inline_source_map};
+use resolver::SectionResolver;
+use utils::{LineOffsets, DebugIdScanner};
 
 
 enum MapRepr {
-    Json(SourceMap),
+    // the token ids of json views in index order are only collected when
+    // the end of a token is needed.  The last field is the debug id which
+    // the sourcemap crate does not parse.
+    Json(SourceMap, OnceLock<Vec<u32>>, Option<String>),
     Mem(MemDb<'static>),
 }
 
@@ -19,6 +33,38 @@ pub struct View {
 
 pub struct Index {
     index: SourceMapIndex,
+    // the sorted token ids of each section for finding the end of tokens
+    sorted_ids: Vec<OnceLock<Vec<u32>>>,
+    // whether the sections pass `verify_index_sections`
+    sections_ok: OnceLock<bool>,
+    debug_id: Option<String>,
+}
+
+/// Builds a source map token by token.
+///
+/// Sources and names are registered first and referenced by their ids
+/// when adding tokens.  Tokens can be added in any order.
+pub struct Builder {
+    file: Option<String>,
+    debug_id: Option<String>,
+    sources: Vec<String>,
+    source_ids: HashMap<String, u32>,
+    source_contents: Vec<Option<String>>,
+    names: Vec<String>,
+    name_ids: HashMap<String, u32>,
+    tokens: Vec<RawToken>,
+}
+
+/// A section of an index.
+#[derive(Debug)]
+pub struct IndexSection<'a> {
+    pub offset_line: u32,
+    pub offset_col: u32,
+    /// The URL of an external map.
+    pub url: Option<&'a str>,
+    /// The number of tokens in the section's map.  This is `None` if the
+    /// section references a map that is not loaded.
+    pub token_count: Option<u32>,
 }
 
 pub enum ViewOrIndex {
@@ -35,29 +81,185 @@ pub struct TokenMatch<'a> {
     pub name: Option<&'a str>,
     pub src: Option<&'a str>,
     pub src_id: u32,
+    /// The minified column where the mapped segment ends.  This is `None`
+    /// if it extends to the end of the line.
+    pub dst_end_col: Option<u32>,
+    /// Indicates that the lookup hit the start of the token exactly
+    /// instead of falling back to a preceding token.
+    pub exact: bool,
+}
+
+/// The direction in which a lookup searches for a token.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Bias {
+    /// Finds the closest token at or before the location.
+    GreatestLowerBound,
+    /// Finds the closest token at or after the location.
+    LeastUpperBound,
+}
+
+/// Options for `View::lookup_token_with_options`.
+#[derive(Debug, Copy, Clone)]
+pub struct LookupOptions {
+    pub bias: Bias,
+    /// Only match tokens on the line of the location.
+    pub same_line: bool,
+    /// Only match tokens at most this many columns away from the location.
+    /// This implies `same_line`.
+    pub max_distance: Option<u32>,
+}
+
+impl Default for LookupOptions {
+    fn default() -> LookupOptions {
+        LookupOptions {
+            bias: Bias::GreatestLowerBound,
+            same_line: false,
+            max_distance: None,
+        }
+    }
+}
+
+/// The contents of a source returned from a view.
+///
+/// Memdb views share the contents with their source cache so repeated
+/// lookups do not decompress the source again.
+#[derive(Debug, Clone)]
+pub enum SourceContents<'a> {
+    Borrowed(&'a str),
+    Shared(Arc<str>),
+}
+
+/// Lines of a source around a given line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceContext {
+    pub pre_lines: Vec<String>,
+    pub line: String,
+    pub post_lines: Vec<String>,
+}
+
+/// A minified stack frame for `View::symbolicate_frames`.
+#[derive(Debug, Clone)]
+pub struct Frame<'a> {
+    pub line: u32,
+    pub col: u32,
+    pub function_name: Option<&'a str>,
+}
+
+/// A stack frame resolved by `View::symbolicate_frames`.
+#[derive(Debug)]
+pub struct SymbolicatedFrame<'a> {
+    pub token: TokenMatch<'a>,
+    pub function_name: Option<&'a str>,
+    pub context: Option<SourceContext>,
+}
+
+fn build_source_context(contents: &str, offsets: &LineOffsets, line: u32, pre: u32, post: u32)
+    -> Option<SourceContext>
+{
+    let get_line = |line| offsets.get_line(contents, line).map(|x| x.to_string());
+    Some(SourceContext {
+        line: get_line(line)?,
+        pre_lines: (line.saturating_sub(pre)..line).filter_map(get_line).collect(),
+        post_lines: (line.saturating_add(1)..)
+            .take(post as usize).map_while(get_line).collect(),
+    })
+}
+
+impl<'a> Deref for SourceContents<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match *self {
+            SourceContents::Borrowed(s) => s,
+            SourceContents::Shared(ref s) => s,
+        }
+    }
+}
+
+/// Moves a location of a section map to the location in the index.  Like
+/// `SourceMapIndex::flatten` this applies the column offset to all lines.
+/// Returns `None` if the location does not fit.
+fn section_to_global(section: &SourceMapSection, (dst_line, dst_col): (u32, u32))
+    -> Option<(u32, u32)>
+{
+    let (off_line, off_col) = section.get_offset();
+    Some((dst_line.checked_add(off_line)?, dst_col.checked_add(off_col)?))
+}
+
+/// Returns the ids of the tokens of a map sorted by minified location.
+fn get_sorted_token_ids(sm: &SourceMap) -> Vec<u32> {
+    sm.index_iter().map(|(_, _, id)| id).collect()
+}
+
+/// Returns the id of the first token after the location.  `index` are the
+/// sorted token ids of the map (see `get_sorted_token_ids`).
+fn get_next_token_id(sm: &SourceMap, index: &[u32], dst: (u32, u32)) -> Option<u32> {
+    let pos = index.partition_point(|&id| {
+        sm.get_token(id).is_some_and(|tok| tok.get_dst() <= dst)
+    });
+    index.get(pos).cloned()
+}
+
+/// Extracts the debug id from a JSON source map.
+fn parse_debug_id(buffer: &[u8]) -> Option<String> {
+    let mut scanner = DebugIdScanner::new();
+    scanner.feed(buffer);
+    scanner.into_debug_id()
+}
+
+/// A reader that looks for the debug id in the data passing through.
+struct DebugIdReader<R> {
+    rdr: R,
+    scanner: DebugIdScanner,
+}
+
+impl<R: Read> Read for DebugIdReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.rdr.read(buf)?;
+        self.scanner.feed(&buf[..len]);
+        Ok(len)
+    }
 }
 
 impl ViewOrIndex {
     pub fn from_slice(buffer: &[u8]) -> Result<ViewOrIndex> {
+        let debug_id = parse_debug_id(buffer);
         Ok(match decode_slice(buffer)? {
-            DecodedMap::Regular(sm) => ViewOrIndex::View(
-                View::from_sourcemap(sm)?),
-            DecodedMap::Index(smi) => ViewOrIndex::Index(
-                Index::from_sourcemap_index(smi)?)
+            DecodedMap::Regular(sm) => ViewOrIndex::View(View {
+                map: MapRepr::Json(sm, OnceLock::new(), debug_id),
+            }),
+            DecodedMap::Index(smi) => ViewOrIndex::Index(Index::new(smi, debug_id)),
         })
     }
+
+    /// Loads a source map from a `data:` URL.
+    pub fn from_data_url(url: &str) -> Result<ViewOrIndex> {
+        ViewOrIndex::from_slice(&decode_data_url(url)?)
+    }
+
+    /// Loads the source map that is inlined into a minified file with a
+    /// `sourceMappingURL` comment holding a `data:` URL.
+    pub fn from_minified_source(source: &[u8]) -> Result<ViewOrIndex> {
+        ViewOrIndex::from_slice(&decode_inline_source_map(source)?)
+    }
 }
 
 impl View {
     pub fn json_from_slice(buffer: &[u8]) -> Result<View> {
         Ok(View {
-            map: MapRepr::Json(SourceMap::from_slice(&buffer)?)
+            map: MapRepr::Json(SourceMap::from_slice(&buffer)?, OnceLock::new(),
+                               parse_debug_id(buffer))
         })
     }
 
     pub fn json_from_reader<R: Read>(rdr: R) -> Result<View> {
+        let mut rdr = DebugIdReader {
+            rdr,
+            scanner: DebugIdScanner::new(),
+        };
+        let sm = SourceMap::from_reader(&mut rdr)?;
         Ok(View {
-            map: MapRepr::Json(SourceMap::from_reader(rdr)?)
+            map: MapRepr::Json(sm, OnceLock::new(), rdr.scanner.into_debug_id())
         })
     }
 
@@ -67,28 +269,179 @@ impl View {
         })
     }
 
+    pub fn memdb_from_vec_validated(vec: Vec<u8>) -> Result<View> {
+        Ok(View {
+            map: MapRepr::Mem(MemDb::from_cow_validated(Cow::Owned(vec))?)
+        })
+    }
+
+    /// Creates a memdb view that borrows the buffer instead of copying it.
+    pub fn memdb_from_slice(buffer: &'static [u8]) -> Result<View> {
+        Ok(View {
+            map: MapRepr::Mem(MemDb::from_slice(buffer)?)
+        })
+    }
+
+    pub fn memdb_from_slice_validated(buffer: &'static [u8]) -> Result<View> {
+        Ok(View {
+            map: MapRepr::Mem(MemDb::from_cow_validated(Cow::Borrowed(buffer))?)
+        })
+    }
+
     pub fn memdb_from_path<P: AsRef<Path>>(path: P) -> Result<View> {
         Ok(View {
             map: MapRepr::Mem(MemDb::from_path(path)?)
         })
     }
 
+    pub fn memdb_from_path_validated<P: AsRef<Path>>(path: P) -> Result<View> {
+        Ok(View {
+            map: MapRepr::Mem(MemDb::from_path_validated(path)?)
+        })
+    }
+
     pub fn from_sourcemap(sm: SourceMap) -> Result<View> {
         Ok(View {
-            map: MapRepr::Json(sm)
+            map: MapRepr::Json(sm, OnceLock::new(), None)
         })
     }
 
+    /// Returns the debug id of the source map.
+    ///
+    /// For JSON maps this is the `debugId` (or legacy `debug_id`) field.
+    /// Memdb views return the debug id that was embedded when dumping.
+    pub fn get_debug_id(&self) -> Option<&str> {
+        match self.map {
+            MapRepr::Json(_, _, ref debug_id) => debug_id.as_deref(),
+            MapRepr::Mem(ref db) => db.get_debug_id(),
+        }
+    }
+
+    /// Embeds the debug id of the view unless the options override it.
+    fn with_debug_id<'b>(&'b self, opts: DumpOptions<'b>) -> DumpOptions<'b> {
+        DumpOptions {
+            debug_id: opts.debug_id.or_else(|| self.get_debug_id()),
+            ..opts
+        }
+    }
+
+    /// Composes the view with the map of the file it was generated from.
+    ///
+    /// `self` maps the minified file to an intermediate file and `other`
+    /// maps that intermediate file to the original sources.  The result
+    /// maps the minified file directly to the original sources with the
+    /// names and source contents of `other`.  Names of `self` are only
+    /// used for tokens that have none in `other`.  Tokens that do not
+    /// resolve to a token on the same line in `other` are kept as unmapped
+    /// tokens so they end the previous mapping.  The composed view keeps
+    /// the file and debug id of `self`.
+    pub fn compose(&self, other: &View) -> Result<View> {
+        // memdb files do not store the file
+        let file = match self.map {
+            MapRepr::Json(ref sm, ..) => sm.get_file(),
+            MapRepr::Mem(_) => None,
+        };
+        let mut builder = SourceMapBuilder::new(file);
+        let opts = LookupOptions {
+            same_line: true,
+            ..Default::default()
+        };
+        for idx in 0..self.get_token_count() {
+            let token = match self.get_token(idx) {
+                Some(token) => token,
+                None => continue,
+            };
+            let inner = match token.src.and_then(|_| {
+                other.lookup_token_with_options(token.src_line, token.src_col, &opts)
+            }) {
+                Some(inner) if inner.src.is_some() => inner,
+                _ => {
+                    builder.add(token.dst_line, token.dst_col, 0, 0, None, None);
+                    continue;
+                }
+            };
+            let raw = builder.add(token.dst_line, token.dst_col, inner.src_line,
+                                  inner.src_col, inner.src, inner.name.or(token.name));
+            if !builder.has_source_contents(raw.src_id) {
+                if let Some(contents) = other.get_source_contents(inner.src_id) {
+                    builder.set_source_contents(raw.src_id, Some(&contents));
+                }
+            }
+        }
+        Ok(View {
+            map: MapRepr::Json(builder.into_sourcemap(), OnceLock::new(),
+                               self.get_debug_id().map(|x| x.to_string())),
+        })
+    }
+
+    /// Dumps the view as memdb.
+    ///
+    /// Memdb views are transcoded which allows dropping names or source
+    /// contents and upgrading files of older format versions.
     pub fn dump_memdb(&self, opts: DumpOptions) -> Result<Vec<u8>> {
+        let opts = self.with_debug_id(opts);
+        match self.map {
+            MapRepr::Json(ref sm, ..) => sourcemap_to_memdb_vec(sm, opts),
+            MapRepr::Mem(ref db) => memdb_to_memdb_vec(db, opts),
+        }
+    }
+
+    /// Dumps the memdb into a writer that does not need to support seeking.
+    pub fn dump_memdb_to_writer<W: Write>(&self, w: W, opts: DumpOptions) -> Result<()> {
+        let opts = self.with_debug_id(opts);
+        match self.map {
+            MapRepr::Json(ref sm, ..) => sourcemap_to_memdb_stream(sm, w, opts),
+            MapRepr::Mem(ref db) => memdb_to_memdb_stream(db, w, opts),
+        }
+    }
+
+    /// Dumps the memdb into a file at the given path.
+    pub fn dump_memdb_to_path<P: AsRef<Path>>(&self, path: P, opts: DumpOptions) -> Result<()> {
+        let opts = self.with_debug_id(opts);
+        let mut w = BufWriter::new(File::create(path)?);
+        match self.map {
+            MapRepr::Json(ref sm, ..) => sourcemap_to_memdb(sm, &mut w, opts)?,
+            MapRepr::Mem(ref db) => memdb_to_memdb(db, &mut w, opts)?,
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Writes the view as JSON source map.
+    ///
+    /// For memdb views the source map is reconstructed from the memdb.
+    /// The debug id is written as `debugId`.
+    pub fn to_json_writer<W: Write>(&self, mut w: W) -> Result<()> {
+        let debug_id = match self.get_debug_id() {
+            Some(debug_id) => debug_id,
+            None => {
+                match self.map {
+                    MapRepr::Json(ref sm, ..) => sm.to_writer(w)?,
+                    MapRepr::Mem(ref db) => db.to_sourcemap()?.to_writer(w)?,
+                }
+                return Ok(());
+            }
+        };
+
+        // sourcemap does not know about debug ids so it is added to the
+        // encoded object
+        let mut buf = vec![];
         match self.map {
-            MapRepr::Json(ref sm) => Ok(sourcemap_to_memdb_vec(sm, opts)),
-            MapRepr::Mem(_) => Err(ErrorKind::AlreadyMemDb.into()),
+            MapRepr::Json(ref sm, ..) => sm.to_writer(&mut buf)?,
+            MapRepr::Mem(ref db) => db.to_sourcemap()?.to_writer(&mut buf)?,
         }
+        let mut value: serde_json::Value = serde_json::from_slice(&buf)
+            .map_err(SourceMapError::from)?;
+        if let Some(obj) = value.as_object_mut() {
+            obj.insert("debugId".into(), serde_json::Value::String(debug_id.into()));
+        }
+        serde_json::to_writer(&mut w, &value).map_err(SourceMapError::from)?;
+        Ok(())
     }
 
     pub fn lookup_token<'a>(&'a self, line: u32, col: u32) -> Option<TokenMatch<'a>> {
         match self.map {
-            MapRepr::Json(ref sm) => {
+            MapRepr::Json(ref sm, ..) => {
                 if let Some(tok) = sm.lookup_token(line, col) {
                     return Some(TokenMatch {
                         src_line: tok.get_src_line(),
@@ -98,6 +451,9 @@ impl View {
                         name: tok.get_name(),
                         src: tok.get_source(),
                         src_id: tok.get_raw_token().src_id,
+                        dst_end_col: self.get_next_dst_col(tok.get_dst_line(),
+                                                           tok.get_dst_col()),
+                        exact: tok.get_dst() == (line, col),
                     });
                 }
             },
@@ -111,6 +467,9 @@ impl View {
                         name: tok.get_name(),
                         src: tok.get_source(),
                         src_id: tok.get_raw_token().src_id,
+                        dst_end_col: self.get_next_dst_col(tok.get_dst_line(),
+                                                           tok.get_dst_col()),
+                        exact: (tok.get_dst_line(), tok.get_dst_col()) == (line, col),
                     });
                 }
             }
@@ -118,53 +477,206 @@ impl View {
         None
     }
 
+    /// Looks up a token with the given options.
+    ///
+    /// The default options find the same tokens as `lookup_token`.
+    pub fn lookup_token_with_options<'a>(&'a self, line: u32, col: u32,
+                                         opts: &LookupOptions) -> Option<TokenMatch<'a>> {
+        let token = match opts.bias {
+            Bias::GreatestLowerBound => self.lookup_token(line, col)?,
+            Bias::LeastUpperBound => match self.lookup_token(line, col) {
+                Some(token) if token.exact => token,
+                _ => {
+                    let id = self.get_next_token_id(line, col)?;
+                    TokenMatch { exact: false, ..self.get_token(id)? }
+                }
+            },
+        };
+        if (opts.same_line || opts.max_distance.is_some()) && token.dst_line != line {
+            return None;
+        }
+        if opts.max_distance.is_some_and(|max| token.dst_col.abs_diff(col) > max) {
+            return None;
+        }
+        Some(token)
+    }
+
+    /// Returns the id of the first token after the given location for
+    /// `get_token`.
+    fn get_next_token_id(&self, line: u32, col: u32) -> Option<u32> {
+        match self.map {
+            MapRepr::Json(ref sm, ref index, _) => {
+                let index = index.get_or_init(|| get_sorted_token_ids(sm));
+                get_next_token_id(sm, index, (line, col))
+            }
+            MapRepr::Mem(ref db) => db.lookup_next_token_idx(line, col),
+        }
+    }
+
+    /// Returns the minified column of the first token after the given
+    /// location if it is on the same line.
+    fn get_next_dst_col(&self, line: u32, col: u32) -> Option<u32> {
+        let id = self.get_next_token_id(line, col)?;
+        let (next_line, next_col) = match self.map {
+            MapRepr::Json(ref sm, ..) => sm.get_token(id)?.get_dst(),
+            MapRepr::Mem(ref db) => {
+                let token = db.get_token(id)?;
+                (token.get_dst_line(), token.get_dst_col())
+            }
+        };
+        if next_line == line {
+            Some(next_col)
+        } else {
+            None
+        }
+    }
+
+    /// Looks up the minified locations of an original location.
+    ///
+    /// This returns all tokens on the original line that point to the
+    /// closest column at or before `col`.  If there is no such column the
+    /// tokens of the first column after it are returned instead.  The
+    /// tokens are ordered by their minified location.
+    pub fn lookup_original<'a>(&'a self, src_id: u32, line: u32, col: u32)
+        -> Vec<TokenMatch<'a>>
+    {
+        let token_ids = match self.map {
+            MapRepr::Json(ref sm, ..) => (0..sm.get_token_count()).filter(|&idx| {
+                sm.get_token(idx).is_some_and(|tok| {
+                    let raw = tok.get_raw_token();
+                    raw.src_id == src_id && raw.src_line == line
+                })
+            }).collect(),
+            MapRepr::Mem(ref db) => db.get_original_line_tokens(src_id, line),
+        };
+        let mut tokens: Vec<_> = token_ids.into_iter()
+            .filter_map(|idx| self.get_token(idx)).collect();
+
+        let src_col = tokens.iter().map(|tok| tok.src_col).filter(|&c| c <= col).max()
+            .or_else(|| tokens.iter().map(|tok| tok.src_col).min());
+        tokens.retain(|tok| Some(tok.src_col) == src_col);
+        tokens.sort_by_key(|tok| (tok.dst_line, tok.dst_col));
+        tokens
+    }
+
     pub fn get_original_function_name(&self, line: u32, col: u32, minified_name: &str,
                                       minified_source: &str) -> Option<&str> {
         match self.map {
-            MapRepr::Json(ref sm) => {
+            MapRepr::Json(ref sm, ..) => {
                 sm.get_original_function_name(line, col, minified_name, minified_source)
             }
-            // mem reprs of sourcemaps currently do not support getting the original function names
-            // for this we would ideally embed that derived information right in the file.
-            MapRepr::Mem(_) => None,
+            // mem reprs use the information precomputed from the minified source
+            // when the memdb was dumped.
+            MapRepr::Mem(ref db) => db.get_original_function_name(line, col, minified_name),
         }
     }
 
-    pub fn get_source_contents<'a>(&'a self, src_id: u32) -> Option<Cow<'a, str>> {
+    pub fn get_source_contents<'a>(&'a self, src_id: u32) -> Option<SourceContents<'a>> {
         match self.map {
-            MapRepr::Json(ref sm) => {
-                sm.get_source_contents(src_id).map(|x| Cow::Borrowed(x))
+            MapRepr::Json(ref sm, ..) => {
+                sm.get_source_contents(src_id).map(SourceContents::Borrowed)
             },
             MapRepr::Mem(ref db) => {
-                db.get_source_contents(src_id).map(|x| Cow::Owned(x))
+                db.get_cached_source_contents(src_id).map(SourceContents::Shared)
             }
         }
     }
 
+    /// Returns a line of a source without its line terminator.
+    pub fn get_source_line(&self, src_id: u32, line: u32) -> Option<String> {
+        self.get_source_context(src_id, line, 0, 0).map(|ctx| ctx.line)
+    }
+
+    /// Returns a line of a source with up to `pre` lines before and `post`
+    /// lines after it.
+    ///
+    /// Memdb views look up the lines in the line offsets stored in the file
+    /// instead of scanning the source.
+    pub fn get_source_context(&self, src_id: u32, line: u32, pre: u32, post: u32)
+        -> Option<SourceContext>
+    {
+        let (contents, offsets) = self.get_source_lines(src_id)?;
+        build_source_context(&contents, &offsets, line, pre, post)
+    }
+
+    fn get_source_lines(&self, src_id: u32) -> Option<(SourceContents<'_>, LineOffsets<'_>)> {
+        let contents = self.get_source_contents(src_id)?;
+        let offsets = match self.map {
+            MapRepr::Json(..) => LineOffsets::from_source(&contents),
+            MapRepr::Mem(ref db) => db.get_line_offsets(src_id)?,
+        };
+        Some((contents, offsets))
+    }
+
+    /// Symbolicates a list of minified stack frames in one go.
+    ///
+    /// For every frame this looks up the token, the original function name
+    /// and `context_lines` lines of context around the original line.  JSON
+    /// views need the minified source to resolve function names while
+    /// memdb views use the function names embedded when they were dumped.
+    /// Frames that cannot be resolved are `None`.
+    pub fn symbolicate_frames<'a>(&'a self, frames: &[Frame], minified_source: Option<&str>,
+                                  context_lines: u32) -> Vec<Option<SymbolicatedFrame<'a>>> {
+        let mut sources = HashMap::new();
+        frames.iter().map(|frame| {
+            let token = self.lookup_token(frame.line, frame.col)?;
+            let function_name = match (frame.function_name, &self.map, minified_source) {
+                (Some(name), &MapRepr::Mem(_), _) => {
+                    self.get_original_function_name(frame.line, frame.col, name, "")
+                }
+                (Some(name), _, Some(source)) => {
+                    self.get_original_function_name(frame.line, frame.col, name, source)
+                }
+                _ => None,
+            };
+            let context = sources.entry(token.src_id)
+                .or_insert_with(|| self.get_source_lines(token.src_id))
+                .as_ref()
+                .and_then(|(contents, offsets)| {
+                    build_source_context(contents, offsets, token.src_line,
+                                         context_lines, context_lines)
+                });
+            Some(SymbolicatedFrame {
+                token,
+                function_name,
+                context,
+            })
+        }).collect()
+    }
+
+    /// Sets the maximum size in bytes of the decompressed source cache.
+    ///
+    /// This only has an effect on memdb views.
+    pub fn set_source_cache_size(&self, size: usize) {
+        if let MapRepr::Mem(ref db) = self.map {
+            db.set_source_cache_size(size);
+        }
+    }
+
     pub fn get_source(&self, src_id: u32) -> Option<&str> {
         match self.map {
-            MapRepr::Json(ref sm) => sm.get_source(src_id),
+            MapRepr::Json(ref sm, ..) => sm.get_source(src_id),
             MapRepr::Mem(ref db) => db.get_source(src_id),
         }
     }
 
     pub fn get_source_count(&self) -> u32 {
         match self.map {
-            MapRepr::Json(ref sm) => sm.get_source_count(),
+            MapRepr::Json(ref sm, ..) => sm.get_source_count(),
             MapRepr::Mem(ref db) => db.get_source_count(),
         }
     }
 
     pub fn get_token_count(&self) -> u32 {
         match self.map {
-            MapRepr::Json(ref sm) => sm.get_token_count(),
+            MapRepr::Json(ref sm, ..) => sm.get_token_count(),
             MapRepr::Mem(ref db) => db.get_token_count(),
         }
     }
 
     pub fn get_token<'a>(&'a self, idx: u32) -> Option<TokenMatch<'a>> {
         match self.map {
-            MapRepr::Json(ref sm) => {
+            MapRepr::Json(ref sm, ..) => {
                 if let Some(tok) = sm.get_token(idx) {
                     return Some(TokenMatch {
                         src_line: tok.get_src_line(),
@@ -174,6 +686,9 @@ impl View {
                         name: tok.get_name(),
                         src: tok.get_source(),
                         src_id: tok.get_raw_token().src_id,
+                        dst_end_col: self.get_next_dst_col(tok.get_dst_line(),
+                                                           tok.get_dst_col()),
+                        exact: true,
                     });
                 }
             },
@@ -187,6 +702,9 @@ impl View {
                         name: tok.get_name(),
                         src: tok.get_source(),
                         src_id: tok.get_raw_token().src_id,
+                        dst_end_col: self.get_next_dst_col(tok.get_dst_line(),
+                                                           tok.get_dst_col()),
+                        exact: true,
                     });
                 }
             }
@@ -195,20 +713,303 @@ impl View {
     }
 }
 
+impl Builder {
+    pub fn new(file: Option<&str>) -> Builder {
+        Builder {
+            file: file.map(|x| x.to_string()),
+            debug_id: None,
+            sources: vec![],
+            source_ids: HashMap::new(),
+            source_contents: vec![],
+            names: vec![],
+            name_ids: HashMap::new(),
+            tokens: vec![],
+        }
+    }
+
+    /// Sets the debug id of the resulting view.
+    pub fn set_debug_id(&mut self, debug_id: Option<&str>) {
+        self.debug_id = debug_id.map(|x| x.to_string());
+    }
+
+    /// Registers a source and returns its id.  Adding the same source
+    /// again returns the existing id.
+    pub fn add_source(&mut self, source: &str) -> u32 {
+        if let Some(&src_id) = self.source_ids.get(source) {
+            return src_id;
+        }
+        let src_id = self.sources.len() as u32;
+        self.sources.push(source.to_string());
+        self.source_contents.push(None);
+        self.source_ids.insert(source.to_string(), src_id);
+        src_id
+    }
+
+    pub fn set_source_contents(&mut self, src_id: u32, contents: Option<&str>) -> Result<()> {
+        match self.source_contents.get_mut(src_id as usize) {
+            Some(slot) => {
+                *slot = contents.map(|x| x.to_string());
+                Ok(())
+            }
+            None => Err(ErrorKind::UnknownId("source".into(), src_id).into()),
+        }
+    }
+
+    /// Registers a name and returns its id.  Adding the same name again
+    /// returns the existing id.
+    pub fn add_name(&mut self, name: &str) -> u32 {
+        if let Some(&name_id) = self.name_ids.get(name) {
+            return name_id;
+        }
+        let name_id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.name_ids.insert(name.to_string(), name_id);
+        name_id
+    }
+
+    /// Adds a token that maps a minified location to an original one.
+    ///
+    /// Tokens without a source only mark the end of the previous token.
+    pub fn add_token(&mut self, dst_line: u32, dst_col: u32, src_line: u32, src_col: u32,
+                     src_id: Option<u32>, name_id: Option<u32>) -> Result<()> {
+        if let Some(src_id) = src_id.filter(|&x| x as usize >= self.sources.len()) {
+            return Err(ErrorKind::UnknownId("source".into(), src_id).into());
+        }
+        if let Some(name_id) = name_id.filter(|&x| x as usize >= self.names.len()) {
+            return Err(ErrorKind::UnknownId("name".into(), name_id).into());
+        }
+        self.tokens.push(RawToken {
+            dst_line,
+            dst_col,
+            src_line,
+            src_col,
+            src_id: src_id.unwrap_or(!0),
+            name_id: name_id.unwrap_or(!0),
+        });
+        Ok(())
+    }
+
+    pub fn get_token_count(&self) -> u32 {
+        self.tokens.len() as u32
+    }
+
+    /// Converts the builder into a view.
+    pub fn into_view(mut self) -> View {
+        // the json encoder expects the tokens in minified order
+        self.tokens.sort_by_key(|x| (x.dst_line, x.dst_col));
+        let source_contents = if self.source_contents.iter().any(|x| x.is_some()) {
+            Some(self.source_contents)
+        } else {
+            None
+        };
+        let sm = SourceMap::new(self.file, self.tokens, self.names, self.sources,
+                                source_contents);
+        View {
+            map: MapRepr::Json(sm, OnceLock::new(), self.debug_id),
+        }
+    }
+}
+
 impl Index {
+    fn new(index: SourceMapIndex, debug_id: Option<String>) -> Index {
+        Index {
+            sorted_ids: (0..index.get_section_count()).map(|_| OnceLock::new()).collect(),
+            sections_ok: OnceLock::new(),
+            index,
+            debug_id,
+        }
+    }
+
     pub fn json_from_slice(buffer: &[u8]) -> Result<Index> {
-        Index::from_sourcemap_index(
-            SourceMapIndex::from_slice(&buffer)?)
+        Ok(Index::new(SourceMapIndex::from_slice(&buffer)?, parse_debug_id(buffer)))
     }
 
     pub fn from_sourcemap_index(smi: SourceMapIndex) -> Result<Index> {
-        Ok(Index {
-            index: smi,
-        })
+        Ok(Index::new(smi, None))
     }
 
+    /// Flattens the index into a view that keeps the debug id.
+    ///
+    /// Indexes with overlapping sections fail with `CannotFlatten`.
     pub fn into_view(self) -> Result<View> {
-        View::from_sourcemap(self.index.flatten()?)
+        verify_index_sections(&self.index)?;
+        Ok(View {
+            map: MapRepr::Json(self.index.flatten()?, OnceLock::new(), self.debug_id)
+        })
+    }
+
+    /// Flattens the index into a view and loads sections that reference
+    /// an external map through the resolver.
+    ///
+    /// Sections the resolver cannot supply fail with `CannotFlatten`.
+    pub fn flatten_with(mut self, resolver: &mut dyn SectionResolver) -> Result<View> {
+        for idx in 0..self.index.get_section_count() {
+            let section = match self.index.get_section_mut(idx) {
+                Some(section) => section,
+                None => continue,
+            };
+            if section.get_sourcemap().is_some() {
+                continue;
+            }
+            let url = match section.get_url() {
+                Some(url) => url.to_string(),
+                None => {
+                    return Err(SourceMapError::CannotFlatten(
+                        format!("section {} has neither a map nor a url", idx)).into());
+                }
+            };
+            match resolver.resolve(&url)? {
+                Some(bytes) => section.set_sourcemap(Some(SourceMap::from_slice(&bytes)?)),
+                None => {
+                    return Err(SourceMapError::CannotFlatten(
+                        format!("could not resolve section url {}", url)).into());
+                }
+            }
+        }
+        self.into_view()
+    }
+
+    /// Dumps the index as memdb without flattening it first.
+    ///
+    /// The tokens of the sections are moved to their offsets and the
+    /// sources and names of all sections are merged.  All sections need
+    /// to have their maps loaded and like for `into_view` they must not
+    /// overlap.
+    pub fn dump_memdb(&self, opts: DumpOptions) -> Result<Vec<u8>> {
+        index_to_memdb_vec(&self.index, self.with_debug_id(opts))
+    }
+
+    /// Dumps the memdb into a writer that does not need to support seeking.
+    pub fn dump_memdb_to_writer<W: Write>(&self, w: W, opts: DumpOptions) -> Result<()> {
+        index_to_memdb_stream(&self.index, w, self.with_debug_id(opts))
+    }
+
+    /// Dumps the memdb into a file at the given path.
+    pub fn dump_memdb_to_path<P: AsRef<Path>>(&self, path: P, opts: DumpOptions) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        index_to_memdb(&self.index, &mut w, self.with_debug_id(opts))?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn with_debug_id<'b>(&'b self, opts: DumpOptions<'b>) -> DumpOptions<'b> {
+        DumpOptions {
+            debug_id: opts.debug_id.or(self.debug_id.as_deref()),
+            ..opts
+        }
+    }
+
+    pub fn get_section_count(&self) -> u32 {
+        self.index.get_section_count()
+    }
+
+    pub fn get_section(&self, idx: u32) -> Option<IndexSection<'_>> {
+        let section = self.index.get_section(idx)?;
+        Some(IndexSection {
+            offset_line: section.get_offset_line(),
+            offset_col: section.get_offset_col(),
+            url: section.get_url(),
+            token_count: section.get_sourcemap().map(|sm| sm.get_token_count()),
+        })
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = IndexSection<'_>> {
+        (0..self.get_section_count()).filter_map(move |idx| self.get_section(idx))
+    }
+
+    /// Looks up the closest token at or before the location without
+    /// flattening the index.
+    ///
+    /// The section is found by its offset and the lookup is delegated to
+    /// its map.  The `src_id` of the result refers to the sources of that
+    /// map.  If the section has no token before the location the
+    /// preceding sections are searched.  `None` is returned if a section
+    /// that needs to be searched references a map that is not loaded, if
+    /// the sections overlap or if the token does not fit into the index.
+    pub fn lookup_token(&self, line: u32, col: u32) -> Option<TokenMatch<'_>> {
+        // overlapping indexes cannot be flattened or dumped either
+        if !*self.sections_ok.get_or_init(|| verify_index_sections(&self.index).is_ok()) {
+            return None;
+        }
+
+        // the sections are sorted by offset
+        let (mut lo, mut hi) = (0, self.index.get_section_count());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.index.get_section(mid)?.get_offset() <= (line, col) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        for section_idx in (0..lo).rev() {
+            let section = self.index.get_section(section_idx)?;
+            let sm = section.get_sourcemap()?;
+            let (off_line, off_col) = section.get_offset();
+            // unsorted sections can start after the location
+            let local_line = line.checked_sub(off_line)?;
+            let tok = match col.checked_sub(off_col) {
+                Some(local_col) => sm.lookup_token(local_line, local_col),
+                // all lines of the section are moved by the column offset
+                // so the location is after the previous line
+                None if local_line > 0 => sm.lookup_token(local_line - 1, !0),
+                None => { return None; }
+            };
+            let tok = match tok {
+                Some(tok) => tok,
+                None => continue,
+            };
+
+            let (dst_line, dst_col) = section_to_global(section, tok.get_dst())?;
+            let sorted_ids = self.sorted_ids.get(section_idx as usize)?
+                .get_or_init(|| get_sorted_token_ids(sm));
+            let end = match get_next_token_id(sm, sorted_ids, tok.get_dst())
+                .and_then(|id| sm.get_token(id))
+            {
+                Some(tok) => section_to_global(section, tok.get_dst()),
+                None => self.get_next_section_dst(section_idx + 1),
+            };
+            return Some(TokenMatch {
+                src_line: tok.get_src_line(),
+                src_col: tok.get_src_col(),
+                dst_line,
+                dst_col,
+                name: tok.get_name(),
+                src: tok.get_source(),
+                src_id: tok.get_raw_token().src_id,
+                dst_end_col: end.filter(|x| x.0 == dst_line).map(|x| x.1),
+                exact: (dst_line, dst_col) == (line, col),
+            });
+        }
+        None
+    }
+
+    /// Returns the location of the first token of the sections starting at
+    /// `section_idx` like it ends up in the flattened map.
+    fn get_next_section_dst(&self, section_idx: u32) -> Option<(u32, u32)> {
+        let mut rv: Option<(u32, u32)> = None;
+        for section in self.index.sections().skip(section_idx as usize) {
+            // the tokens of later sections cannot come first
+            if rv.is_some_and(|dst| dst <= section.get_offset()) {
+                break;
+            }
+            let dst = match section.get_sourcemap() {
+                // tokens that do not fit cannot end anything
+                Some(sm) => match sm.index_iter().next()
+                    .and_then(|(dst_line, dst_col, _)| {
+                        section_to_global(section, (dst_line, dst_col))
+                    })
+                {
+                    Some(dst) => dst,
+                    None => continue,
+                },
+                // the map is not loaded so the end is not known exactly
+                None => section.get_offset(),
+            };
+            rv = Some(rv.map_or(dst, |x| x.min(dst)));
+        }
+        rv
     }
 
     pub fn can_flatten(&self) -> bool {
@@ -0,0 +1,257 @@
+use regex::Regex;
+use serde_json;
+
+
+lazy_static! {
+    static ref ANCHORED_IDENT_RE: Regex = Regex::new(
+        r#"(?x)
+            ^
+            \s*
+            ([\d\p{Lu}\p{Ll}\p{Lt}\p{Lm}\p{Lo}\p{Nl}$_]
+            [\d\p{Lu}\p{Ll}\p{Lt}\p{Lm}\p{Lo}\p{Nl}\p{Mn}\p{Mc}\p{Nd}\p{Pc}$_]*)
+        "#).unwrap();
+}
+
+/// Returns the javascript identifier at the start of a string.
+///
+/// This matches the logic `sourcemap` uses for original function names.
+pub fn get_javascript_token(source: &str) -> Option<&str> {
+    ANCHORED_IDENT_RE.captures(source).and_then(|m| m.get(1)).map(|m| m.as_str())
+}
+
+/// Walks over a minified source file to find the identifiers at token
+/// positions.
+///
+/// Columns are UTF-16 offsets as used by sourcemaps.  Positions should be
+/// visited in ascending order as the cursor only scans forward within a
+/// line and restarts from the beginning of the line otherwise.
+pub struct SourceCursor<'a> {
+    lines: Vec<&'a str>,
+    line: usize,
+    col: usize,
+    byte_offset: usize,
+}
+
+impl<'a> SourceCursor<'a> {
+    pub fn new(source: &'a str) -> SourceCursor<'a> {
+        SourceCursor {
+            lines: source.lines().collect(),
+            line: !0,
+            col: 0,
+            byte_offset: 0,
+        }
+    }
+
+    /// Returns the identifier at the given position if there is one.
+    pub fn get_token(&mut self, line: u32, col: u32) -> Option<&'a str> {
+        let (line, col) = (line as usize, col as usize);
+        let source_line = *self.lines.get(line)?;
+        if line != self.line || col < self.col {
+            self.line = line;
+            self.col = 0;
+            self.byte_offset = 0;
+        }
+
+        for c in source_line[self.byte_offset..].chars() {
+            if self.col >= col {
+                break;
+            }
+            self.byte_offset += c.len_utf8();
+            self.col += c.len_utf16();
+        }
+
+        if self.byte_offset >= source_line.len() {
+            None
+        } else {
+            get_javascript_token(&source_line[self.byte_offset..])
+        }
+    }
+}
+
+/// The byte offsets at which the lines of a source start.
+///
+/// Lines are terminated by `\n`, `\r\n` or `\r`.  Memdb files store the
+/// offsets as little-endian `u32` values so they can be used without
+/// scanning the source.
+pub enum LineOffsets<'a> {
+    Computed(Vec<u32>),
+    Encoded(&'a [u8]),
+}
+
+impl<'a> LineOffsets<'a> {
+    pub fn from_source(source: &str) -> LineOffsets<'a> {
+        let bytes = source.as_bytes();
+        let mut rv = vec![0];
+        for (idx, &b) in bytes.iter().enumerate() {
+            if b == b'\n' || (b == b'\r' && bytes.get(idx + 1) != Some(&b'\n')) {
+                rv.push(idx as u32 + 1);
+            }
+        }
+        LineOffsets::Computed(rv)
+    }
+
+    /// Returns the encoded offsets as they are stored in memdb files.
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            LineOffsets::Computed(ref offsets) => {
+                offsets.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect()
+            }
+            LineOffsets::Encoded(bytes) => bytes.to_vec(),
+        }
+    }
+
+    /// Returns the number of lines.
+    pub fn len(&self) -> usize {
+        match *self {
+            LineOffsets::Computed(ref offsets) => offsets.len(),
+            LineOffsets::Encoded(bytes) => bytes.len() / 4,
+        }
+    }
+
+    fn get(&self, line: usize) -> Option<usize> {
+        match *self {
+            LineOffsets::Computed(ref offsets) => offsets.get(line).map(|&x| x as usize),
+            LineOffsets::Encoded(bytes) => {
+                let start = line.checked_mul(4)?;
+                let raw = bytes.get(start..start.checked_add(4)?)?;
+                Some(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize)
+            }
+        }
+    }
+
+    /// Returns a line of the source without its line terminator.
+    pub fn get_line<'s>(&self, source: &'s str, line: u32) -> Option<&'s str> {
+        let line = line as usize;
+        let start = self.get(line)?;
+        let end = if line + 1 < self.len() {
+            self.get(line + 1)?
+        } else {
+            source.len()
+        };
+        let rv = source.get(start..end)?;
+        Some(rv.strip_suffix("\r\n")
+            .or_else(|| rv.strip_suffix('\n'))
+            .or_else(|| rv.strip_suffix('\r'))
+            .unwrap_or(rv))
+    }
+}
+
+/// Finds the debug id of a JSON source map while the data passes by.
+///
+/// This only tracks the nesting of the JSON data instead of parsing it so
+/// the source map itself is still decoded by `sourcemap`.  Only keys of
+/// the top-level object are considered and `debugId` takes precedence
+/// over the legacy `debug_id`.  The value is decoded with `serde_json`.
+#[derive(Default)]
+pub struct DebugIdScanner {
+    depth: u32,
+    done: bool,
+    in_string: bool,
+    escaped: bool,
+    expect_key: bool,
+    // the string at the top-level that is currently captured
+    capture: Option<Vec<u8>>,
+    key: Option<Vec<u8>>,
+    debug_id: Option<Vec<u8>>,
+    legacy_debug_id: Option<Vec<u8>>,
+}
+
+impl DebugIdScanner {
+    pub fn new() -> DebugIdScanner {
+        Default::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        for &b in data {
+            if self.done {
+                break;
+            }
+            self.feed_byte(b);
+        }
+    }
+
+    fn feed_byte(&mut self, b: u8) {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if b == b'"' {
+                self.in_string = false;
+                self.finish_string();
+                return;
+            } else if b == b'\\' {
+                self.escaped = true;
+            }
+            if let Some(ref mut capture) = self.capture {
+                capture.push(b);
+            }
+            return;
+        }
+
+        // skip a garbage header before the top-level object
+        if self.depth == 0 {
+            if b == b'{' {
+                self.depth = 1;
+                self.expect_key = true;
+            }
+            return;
+        }
+
+        match b {
+            b'"' => {
+                self.in_string = true;
+                if self.depth == 1 && (self.expect_key || self.is_debug_id_key()) {
+                    self.capture = Some(vec![]);
+                }
+            }
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' => {
+                self.depth -= 1;
+                self.done = self.depth == 0;
+            }
+            b':' if self.depth == 1 => self.expect_key = false,
+            b',' if self.depth == 1 => {
+                self.expect_key = true;
+                self.key = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn is_debug_id_key(&self) -> bool {
+        match self.key.as_deref() {
+            Some(b"debugId") => self.debug_id.is_none(),
+            Some(b"debug_id") => self.legacy_debug_id.is_none(),
+            _ => false,
+        }
+    }
+
+    fn finish_string(&mut self) {
+        let value = match self.capture.take() {
+            Some(value) => value,
+            None => return,
+        };
+        if self.expect_key {
+            self.key = Some(value);
+        } else if self.key.as_deref() == Some(b"debugId") {
+            self.debug_id = Some(value);
+        } else {
+            self.legacy_debug_id = Some(value);
+        }
+    }
+
+    /// Returns the debug id if one was found.
+    pub fn into_debug_id(self) -> Option<String> {
+        self.debug_id.or(self.legacy_debug_id)
+            .and_then(|raw| decode_json_string(&raw))
+            .filter(|x| !x.is_empty())
+    }
+}
+
+/// Decodes the contents of a JSON string literal.
+fn decode_json_string(raw: &[u8]) -> Option<String> {
+    let mut literal = Vec::with_capacity(raw.len() + 2);
+    literal.push(b'"');
+    literal.extend_from_slice(raw);
+    literal.push(b'"');
+    serde_json::from_slice(&literal).ok()
+}
@@ -0,0 +1,71 @@
+import base64
+
+import pytest
+
+from libsourcemap import View, Index, from_data_url, from_minified_source, \
+    BadDataUrl, UnsupportedMediaType, NoInlineSourceMap
+
+try:
+    from urllib.parse import quote
+except ImportError:
+    from urllib import quote
+
+from testutils import get_fixtures, verify_index, verify_token_equivalence
+
+
+def test_base64_data_url():
+    source, min_map = get_fixtures('jquery')
+    for prefix in [b'data:application/json;base64,',
+                   b'data:application/json;charset=utf-8;base64,',
+                   b'DATA:Application/JSON;Charset=UTF-8;base64,']:
+        view = from_data_url(prefix + base64.b64encode(min_map))
+        verify_index(view, source)
+        verify_token_equivalence(view, View.from_json(min_map))
+
+
+def test_percent_encoded_data_url():
+    source, min_map = get_fixtures('coolstuff')
+    view = from_data_url('data:application/json;charset=utf-8,' +
+                         quote(min_map))
+    verify_index(view, source)
+    verify_token_equivalence(view, View.from_json(min_map))
+
+
+def test_minified_source():
+    source, minified, min_map = get_fixtures('coolstuff', with_minified=True)
+    minified = minified.rsplit(b'\n', 1)[0] + \
+        b'\n//# sourceMappingURL=data:application/json;base64,' + \
+        base64.b64encode(min_map) + b'\n'
+    view = from_minified_source(minified)
+    verify_index(view, source)
+
+    with pytest.raises(NoInlineSourceMap):
+        with open('tests/fixtures/coolstuff.min.js', 'rb') as f:
+            from_minified_source(f.read())
+    with pytest.raises(NoInlineSourceMap):
+        from_minified_source(b'foo();')
+
+
+def test_index_data_url():
+    with open('tests/fixtures/indexed.sourcemap.js', 'rb') as f:
+        min_map = f.read()
+    url = b'data:application/json;base64,' + base64.b64encode(min_map)
+    assert isinstance(from_data_url(url, auto_flatten=False,
+                                    raise_for_index=False), Index)
+    assert isinstance(from_data_url(url), View)
+
+
+def test_bad_data_urls():
+    for url in ['data:text/plain;base64,e30=',
+                'data:,{}',
+                'data:application/javascript,{}',
+                'data:application/json;charset=latin1,{}']:
+        with pytest.raises(UnsupportedMediaType):
+            from_data_url(url)
+
+    for url in ['http://example.com/foo.map',
+                'data:application/json;base64',
+                'data:application/json;base64,e30!']:
+        with pytest.raises(BadDataUrl) as excinfo:
+            from_data_url(url)
+        assert excinfo.type is BadDataUrl
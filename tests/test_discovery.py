@@ -0,0 +1,85 @@
+from libsourcemap import discover_references
+
+
+def test_fixtures():
+    for name, url in [
+        ('coolstuff', 'tests/fixtures/coolstuff.min.map'),
+        ('indexed', 'indexed.sourcemap.js'),
+        ('jquery', 'jquery.min.map'),
+        ('traceback-unicode', 'test.map'),
+    ]:
+        with open('tests/fixtures/%s.min.js' % name, 'rb') as f:
+            refs = discover_references(f.read())
+        assert refs.source_map_url == url
+        assert not refs.source_map_inline
+        assert refs.debug_id is None
+
+
+def test_comment_forms():
+    for source in [
+        b'foo();\n//# sourceMappingURL=foo.js.map',
+        b'foo();\n//@ sourceMappingURL=foo.js.map\n',
+        b'foo();\r\n//# sourceMappingURL=foo.js.map\r\n',
+        b'a{}\n/*# sourceMappingURL=foo.js.map */',
+        b'a{}\n/*# sourceMappingURL=foo.js.map*/\n',
+        b'//# sourceMappingURL=old.js.map\nfoo();\n'
+        b'//# sourceMappingURL=foo.js.map',
+    ]:
+        refs = discover_references(source)
+        assert refs.source_map_url == 'foo.js.map', source
+
+    assert discover_references(b'foo();') == (None, False, None)
+    assert discover_references(b'//# sourceMappingURL=\n') == \
+        (None, False, None)
+
+
+def test_url_resolution():
+    source = b'foo();\n//# sourceMappingURL=../maps/foo.js.map'
+    assert discover_references(source).source_map_url == '../maps/foo.js.map'
+    refs = discover_references(source, url='https://example.com/js/foo.js')
+    assert refs.source_map_url == 'https://example.com/maps/foo.js.map'
+
+    source = b'foo();\n//# sourceMappingURL=https://cdn.example.com/foo.map'
+    refs = discover_references(source, url='https://example.com/js/foo.js')
+    assert refs.source_map_url == 'https://cdn.example.com/foo.map'
+
+    # urls that are not absolute cannot be resolved against
+    refs = discover_references(source.replace(b'https://cdn.example.com', b''),
+                               url='js/foo.js')
+    assert refs.source_map_url == '/foo.map'
+
+
+def test_inline_and_debug_id():
+    source = (
+        b'foo();\n'
+        b'//# debugId=85314830-023f-4cf1-a267-535f4e37bb17\n'
+        b'//# sourceMappingURL=data:application/json;base64,e30=\n'
+    )
+    refs = discover_references(source, url='https://example.com/foo.js')
+    assert refs.source_map_url == 'data:application/json;base64,e30='
+    assert refs.source_map_inline
+    assert refs.debug_id == '85314830-023f-4cf1-a267-535f4e37bb17'
+
+    refs = discover_references(
+        b'a{}\n/*# debugId=85314830-023f-4cf1-a267-535f4e37bb17 */')
+    assert refs.source_map_url is None
+    assert refs.debug_id == '85314830-023f-4cf1-a267-535f4e37bb17'
+
+
+def test_comments_in_code():
+    # comments in string literals are not references
+    source = b"var a='//# sourceMappingURL=evil.map';\n"
+    assert discover_references(source, url='https://x/a/foo.js') == \
+        (None, False, None)
+    source = (b'var a="//# debugId=00000000-0000-0000-0000-000000000000";\n'
+              b'//# sourceMappingURL=foo.js.map\n')
+    assert discover_references(source) == ('foo.js.map', False, None)
+
+    # neither are comments that follow code on the same line
+    source = (b'//# sourceMappingURL=foo.js.map\n'
+              b'foo(); //# sourceMappingURL=bar.js.map\n')
+    assert discover_references(source).source_map_url == 'foo.js.map'
+
+    # indented comments are fine and the url ends before quotes
+    source = b'foo();\n  //# sourceMappingURL=foo.js.map"\n'
+    assert discover_references(source).source_map_url == 'foo.js.map'
@@ -35,3 +35,87 @@ def test_unicode_stacktrace():
     for line, col, minified, match in stacktrace:
         rv = view.get_original_function_name(line, col, minified, min_source)
         assert rv == match
+
+
+def test_stacktrace_memdb():
+    _, min_source, min_map = get_fixtures('traceback', with_minified=True)
+    view = View.from_json(min_map)
+    mem_view = View.from_memdb(view.dump_memdb(minified_source=min_source))
+
+    stacktrace = [
+        (0, 63, u'e', 'onFailure'),
+        (0, 135, 'r', 'invoke'),
+        (0, 182, 'i', 'test'),
+        (0, 244, 'nonexisting', None),
+    ]
+
+    for line, col, minified, match in stacktrace:
+        rv = mem_view.get_original_function_name(line, col, minified,
+                                                 min_source)
+        assert rv == match
+
+
+def test_unicode_stacktrace_memdb():
+    _, min_source, min_map = get_fixtures('traceback-unicode',
+                                          with_minified=True)
+    view = View.from_json(min_map)
+    mem_view = View.from_memdb(view.dump_memdb(minified_source=min_source))
+
+    stacktrace = [
+        (0, 63, u'e', 'onFailure'),
+        (0, 135, 'r', 'invoke'),
+        (0, 191, 'i', u'ÿ'),
+        (0, 244, 'nonexisting', None),
+    ]
+
+    for line, col, minified, match in stacktrace:
+        rv = mem_view.get_original_function_name(line, col, minified,
+                                                 min_source)
+        assert rv == match
+
+
+def test_memdb_without_minified_source():
+    _, min_source, min_map = get_fixtures('traceback', with_minified=True)
+    view = View.from_json(min_map)
+    mem_view = View.from_memdb(view.dump_memdb())
+    assert mem_view.get_original_function_name(
+        0, 63, 'e', min_source) is None
+
+
+def test_symbolicate_frames():
+    source, min_source, min_map = get_fixtures('traceback', with_minified=True)
+    view = View.from_json(min_map)
+    mem_view = View.from_memdb(view.dump_memdb(minified_source=min_source))
+    # the line after the final newline is a line of its own
+    source_lines = source.decode('utf-8').split(u'\n')
+
+    stacktrace = [
+        (0, 63, u'e', 'onFailure'),
+        (0, 135, 'r', 'invoke'),
+        (0, 182, 'i', 'test'),
+        (0, 244, 'nonexisting', None),
+        (0, 244, None, None),
+    ]
+    frames = [x[:3] for x in stacktrace] + [(-1, 0, None)]
+
+    for v in view, mem_view:
+        rv = v.symbolicate_frames(frames, minified_source=min_source,
+                                  context_lines=2)
+        assert len(rv) == len(frames)
+        assert rv[-1] is None
+        for (line, col, _, match), frame in zip(stacktrace, rv):
+            token = v.lookup_token(line, col)
+            assert frame.token == token
+            assert frame.function_name == match
+            assert frame.context_line == source_lines[token.src_line]
+            assert frame.pre_context == \
+                source_lines[max(token.src_line - 2, 0):token.src_line]
+            assert frame.post_context == \
+                source_lines[token.src_line + 1:token.src_line + 3]
+
+    # json views cannot resolve function names without the minified source
+    rv = view.symbolicate_frames(frames[:1], context_lines=0)
+    assert rv[0].function_name is None
+    assert rv[0].pre_context == rv[0].post_context == []
+    assert mem_view.symbolicate_frames(frames[:1])[0].function_name == \
+        'onFailure'
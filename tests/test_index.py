@@ -1,6 +1,13 @@
-from libsourcemap import Index
+import json
+import os
+import shutil
+import tempfile
 
-from testutils import verify_index, verify_token_search
+import pytest
+
+from libsourcemap import View, Index, IndexSection, CannotFlatten
+
+from testutils import verify_index, verify_token_search, make_sourcemap
 
 
 def test_load_index():
@@ -20,3 +27,232 @@ def test_load_index():
         'file2.js': f2,
     })
     verify_token_search(view)
+
+
+def make_external_index():
+    with open('tests/fixtures/indexed.sourcemap.js', 'rb') as f:
+        index_map = json.load(f)
+    maps = {}
+    for idx, section in enumerate(index_map['sections']):
+        url = 'maps/section%d.map' % idx
+        maps[url] = json.dumps(section.pop('map')).encode('utf-8')
+        section['url'] = url
+    return json.dumps(index_map), maps
+
+
+def make_index(sections):
+    """Builds an index from ``(offset_line, offset_col, tokens)`` tuples
+    with tokens like for `make_sourcemap`.
+    """
+    return json.dumps({
+        'version': 3,
+        'sections': [{
+            'offset': {'line': line, 'column': col},
+            'map': json.loads(make_sourcemap(tokens)),
+        } for line, col, tokens in sections],
+    })
+
+
+def test_flatten_with():
+    with open('tests/fixtures/indexed.sourcemap.js', 'rb') as f:
+        expected = list(Index.from_json(f.read()).into_view())
+    index_map, maps = make_external_index()
+
+    idx = Index.from_json(index_map)
+    assert not idx.can_flatten
+    view = idx.flatten_with(maps.get)
+    assert list(view) == expected
+
+    with pytest.raises(CannotFlatten):
+        Index.from_json(index_map).flatten_with(lambda url: None)
+
+    def failing_resolver(url):
+        raise LookupError(url)
+    with pytest.raises(LookupError):
+        Index.from_json(index_map).flatten_with(failing_resolver)
+
+
+def test_flatten_with_directory():
+    with open('tests/fixtures/indexed.sourcemap.js', 'rb') as f:
+        expected = list(Index.from_json(f.read()).into_view())
+    index_map, maps = make_external_index()
+
+    base = tempfile.mkdtemp()
+    try:
+        os.mkdir(os.path.join(base, 'maps'))
+        for url, data in maps.items():
+            with open(os.path.join(base, url), 'wb') as f:
+                f.write(data)
+        view = Index.from_json(index_map).flatten_with_directory(base)
+        assert list(view) == expected
+
+        # urls must not escape the directory
+        escaping_map = index_map.replace('maps/', 'maps/../../%s/maps/'
+                                         % os.path.basename(base))
+        with pytest.raises(CannotFlatten):
+            Index.from_json(escaping_map).flatten_with_directory(base)
+    finally:
+        shutil.rmtree(base)
+
+    with pytest.raises(CannotFlatten):
+        Index.from_json(index_map).flatten_with_directory(base)
+
+
+def test_lookup_token():
+    with open('tests/fixtures/indexed.sourcemap.js', 'rb') as f:
+        index_map = f.read()
+    idx = Index.from_json(index_map)
+    view = Index.from_json(index_map).into_view()
+
+    for token in view:
+        for col in range(token.dst_col, token.dst_col + 3):
+            match = idx.lookup_token(token.dst_line, col)
+            expected = view.lookup_token(token.dst_line, col)
+            assert match[:5] == expected[:5]
+            assert match.name == expected.name
+            assert match.exact == expected.exact
+            assert match.dst_end_col == expected.dst_end_col
+    # the second line starts the second section
+    assert idx.lookup_token(1, 0).src == 'file2.js'
+    assert idx.lookup_token(1, 0).src_id == 0
+    assert idx.lookup_token(5, 0)[:5] == view.lookup_token(5, 0)[:5]
+
+    index_map, maps = make_external_index()
+    idx = Index.from_json(index_map)
+    assert idx.lookup_token(0, 0) is None
+
+
+def test_lookup_token_end():
+    index_map = make_index([
+        (0, 0, [(0, 0, 'a.js', 0, 0, None), (0, 5, 'a.js', 1, 0, None)]),
+        # the first token of this section comes after its offset
+        (0, 10, [(0, 5, 'b.js', 0, 0, None)]),
+        (0, 30, [(0, 0, 'c.js', 0, 0, None)]),
+    ])
+    idx = Index.from_json(index_map)
+    view = Index.from_json(index_map).into_view()
+    for col in range(40):
+        match = idx.lookup_token(0, col)
+        expected = view.lookup_token(0, col)
+        assert match[:5] == expected[:5]
+        assert match.dst_end_col == expected.dst_end_col
+    assert idx.lookup_token(0, 12).src == 'a.js'
+    assert idx.lookup_token(0, 12).dst_end_col == 15
+
+
+def test_column_offsets():
+    # like flattening the column offset applies to all lines of a section
+    index_map = make_index([
+        (0, 0, [(0, 0, 'a.js', 0, 0, 'a')]),
+        (0, 50, [(0, 0, 'b.js', 0, 0, 'b'), (1, 0, 'b.js', 1, 0, None),
+                 (1, 10, 'b.js', 1, 4, None), (2, 3, 'b.js', 2, 0, None)]),
+    ])
+    idx = Index.from_json(index_map)
+    view = Index.from_json(index_map).into_view()
+    assert view.lookup_token(1, 50)[:5] == (1, 50, 'b.js', 1, 0)
+
+    assert idx.dump_memdb() == view.dump_memdb()
+    assert list(View.from_memdb(idx.dump_memdb())) == list(view)
+    for line in range(3):
+        for col in range(70):
+            match = idx.lookup_token(line, col)
+            expected = view.lookup_token(line, col)
+            assert match[:5] == expected[:5]
+            assert match.dst_end_col == expected.dst_end_col
+
+
+def test_overlapping_sections():
+    # the first section has a token after the offset of the second one
+    index_map = make_index([
+        (0, 0, [(0, 0, 'a.js', 0, 0, None), (0, 20, 'a.js', 1, 0, None)]),
+        (0, 10, [(0, 0, 'b.js', 0, 0, None), (0, 5, 'b.js', 1, 0, None)]),
+    ])
+    idx = Index.from_json(index_map)
+    with pytest.raises(CannotFlatten):
+        idx.dump_memdb()
+    with pytest.raises(CannotFlatten):
+        Index.from_json(index_map).into_view()
+    for col in range(30):
+        assert idx.lookup_token(0, col) is None
+
+    # sections that only touch resolve the same in all representations
+    index_map = make_index([
+        (0, 0, [(0, 0, 'a.js', 0, 0, None), (0, 9, 'a.js', 1, 0, None)]),
+        (0, 10, [(0, 0, 'b.js', 0, 0, None), (0, 5, 'b.js', 1, 0, None)]),
+    ])
+    idx = Index.from_json(index_map)
+    view = Index.from_json(index_map).into_view()
+    mem_view = View.from_memdb(idx.dump_memdb())
+    assert len(mem_view) == len(view) == 4
+    for col in range(30):
+        expected = view.lookup_token(0, col)
+        assert mem_view.lookup_token(0, col)[:5] == expected[:5]
+        assert idx.lookup_token(0, col)[:5] == expected[:5]
+
+
+def test_lookup_token_unsorted():
+    idx = Index.from_json(make_index([
+        (0, 20, [(0, 0, 'a.js', 0, 0, None)]),
+        (0, 0, [(0, 10, 'b.js', 0, 0, None)]),
+    ]))
+    assert idx.lookup_token(0, 5) is None
+
+    idx = Index.from_json(make_index([
+        (3, 0, [(0, 0, 'a.js', 0, 0, None)]),
+        (0, 0, [(2, 0, 'b.js', 0, 0, None)]),
+    ]))
+    assert idx.lookup_token(1, 0) is None
+
+
+def test_large_offsets():
+    # tokens moved past the largest location cannot be matched
+    idx = Index.from_json(make_index([
+        (0, 0, [(0, 0, 'a.js', 0, 0, None)]),
+        (0, 0xfffffff0, [(0, 0, 'b.js', 0, 0, None),
+                         (0, 0x20, 'b.js', 1, 0, None),
+                         (1, 0x20, 'b.js', 2, 0, None)]),
+    ]))
+    match = idx.lookup_token(0, 0xfffffff5)
+    assert match[:5] == (0, 0xfffffff0, 'b.js', 0, 0)
+    assert match.dst_end_col is None
+    assert idx.lookup_token(1, 0xffffffff) is None
+
+
+def test_sections():
+    with open('tests/fixtures/indexed.sourcemap.js', 'rb') as f:
+        idx = Index.from_json(f.read())
+    assert idx.get_sections() == [
+        IndexSection(0, 0, None, 8),
+        IndexSection(1, 0, None, 27),
+    ]
+
+    index_map, maps = make_external_index()
+    assert Index.from_json(index_map).get_sections() == [
+        IndexSection(0, 0, 'maps/section0.map', None),
+        IndexSection(1, 0, 'maps/section1.map', None),
+    ]
+
+
+def test_dump_memdb():
+    with open('tests/fixtures/indexed.sourcemap.js', 'rb') as f:
+        index_map = f.read()
+    with open('tests/fixtures/indexed.min.js', 'rb') as f:
+        minified = f.read().decode('utf-8')
+    idx = Index.from_json(index_map)
+    view = Index.from_json(index_map).into_view()
+
+    for kwargs in [{}, dict(minified_source=minified),
+                   dict(with_names=False, with_source_contents=False)]:
+        memdb = idx.dump_memdb(**kwargs)
+        assert memdb == view.dump_memdb(**kwargs)
+    mem_view = View.from_memdb(idx.dump_memdb(), validate=True)
+    assert list(mem_view) == list(view)
+
+    with tempfile.NamedTemporaryFile() as f:
+        idx.dump_memdb_to_path(f.name)
+        with open(f.name, 'rb') as f2:
+            assert f2.read() == idx.dump_memdb()
+
+    index_map, maps = make_external_index()
+    with pytest.raises(CannotFlatten):
+        Index.from_json(index_map).dump_memdb()
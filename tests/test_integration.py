@@ -1,7 +1,12 @@
-from libsourcemap import View, Index, from_json, IndexedSourceMap
+import json
+
+import pytest
+
+from libsourcemap import View, Index, Builder, from_json, IndexedSourceMap, \
+    UnknownId
 
 from testutils import get_fixtures, verify_index, verify_token_equivalence, \
-    verify_token_search
+    verify_token_search, make_sourcemap
 
 
 def test_jquery():
@@ -139,3 +144,364 @@ def test_memdb_dumping():
     assert full_mem_index.get_source_contents(0) == \
         nonames_mem_index.get_source_contents(0)
     assert nosource_mem_index.get_source_contents(0) is None
+
+
+def test_memdb_v1_compat():
+    source, min_map = get_fixtures('react-dom-full')
+    index = View.from_json(min_map)
+    with open('tests/fixtures/react-dom-full.min.v1.memdb', 'rb') as f:
+        v1_index = View.from_memdb(f.read())
+    verify_index(v1_index, source)
+    verify_token_equivalence(index, v1_index)
+    assert v1_index.get_source_contents(0) == index.get_source_contents(0)
+
+
+def test_memdb_large_locations():
+    min_map = (
+        b'{"version":3,"sources":["foo.js"],"names":["bar"],'
+        b'"mappings":"' + b';' * 200000 + b'AAg0mMAA"}'
+    )
+    index = View.from_json(min_map)
+    mem_index = View.from_memdb(index.dump_memdb())
+    verify_token_equivalence(index, mem_index)
+
+    token = mem_index.lookup_token(200000, 10)
+    assert token.dst_line == 200000
+    assert token.src_line == 200000
+    assert token.name == 'bar'
+
+
+def test_memdb_many_sources_and_names():
+    sources = ['"src%d.js"' % x for x in range(20001)]
+    names = ['"name%d"' % x for x in range(300001)]
+    min_map = (
+        '{"version":3,"sources":[%s],"names":[%s],'
+        '"mappings":"AAAAA,CginBAAg+pS"}' % (','.join(sources), ','.join(names))
+    ).encode('utf-8')
+    index = View.from_json(min_map)
+    mem_index = View.from_memdb(index.dump_memdb())
+    verify_token_equivalence(index, mem_index)
+
+    token = mem_index.lookup_token(0, 1)
+    assert token.src_id == 20000
+    assert token.src == 'src20000.js'
+    assert token.name == 'name300000'
+
+
+def test_empty_source_contents_memdb():
+    index = View.from_json(
+        b'{"version":3,"sources":["foo.js"],"sourcesContent":[""],'
+        b'"names":[],"mappings":"AAAA"}')
+    mem_index = View.from_memdb(index.dump_memdb())
+    assert index.get_source_contents(0) == b''
+    assert mem_index.get_source_contents(0) == b''
+
+
+def test_memdb_to_json():
+    source, min_map = get_fixtures('react-dom-full')
+    index = View.from_json(min_map)
+    mem_index = View.from_memdb(index.dump_memdb())
+
+    json_index = View.from_json(mem_index.dump_json())
+    verify_index(json_index, source)
+    verify_token_equivalence(index, json_index)
+    for src_id, source_name in index.iter_sources():
+        assert json_index.get_source_name(src_id) == source_name
+        assert json_index.get_source_contents(src_id) == \
+            index.get_source_contents(src_id)
+
+    nonames_index = View.from_json(View.from_memdb(index.dump_memdb(
+        with_names=False, with_source_contents=False)).dump_json())
+    for token in nonames_index:
+        assert token.name is None
+    assert nonames_index.get_source_contents(0) is None
+
+    # json views are dumped as they are
+    verify_token_equivalence(index, View.from_json(index.dump_json()))
+
+
+def test_source_lines():
+    index = View.from_json(
+        b'{"version":3,"sources":["foo.js","bar.js"],'
+        b'"sourcesContent":["a\\r\\nb\\rc\\n\\nd\\u00e4\\n",null],'
+        b'"names":[],"mappings":"AAAA"}')
+    lines = [u'a', u'b', u'c', u'', u'd\xe4', u'']
+
+    for view in index, View.from_memdb(index.dump_memdb()):
+        for idx, line in enumerate(lines):
+            assert view.get_source_line(0, idx) == line
+        assert view.get_source_line(0, len(lines)) is None
+        assert view.get_source_line(0, -1) is None
+        assert view.get_source_line(1, 0) is None
+
+        assert view.get_source_context(0, 2, 1, 1) == ([u'b'], u'c', [u''])
+        assert view.get_source_context(0, 0) == ([], u'a', lines[1:])
+        assert view.get_source_context(0, 5, 10, 10) == (lines[:5], u'', [])
+        assert view.get_source_context(0, 1, 0, 0) == ([], u'b', [])
+        assert view.get_source_context(0, 6) is None
+
+
+def test_source_lines_memdb_v1():
+    source, min_map = get_fixtures('react-dom-full')
+    index = View.from_json(min_map)
+    with open('tests/fixtures/react-dom-full.min.v1.memdb', 'rb') as f:
+        v1_index = View.from_memdb(f.read())
+    mem_index = View.from_memdb(index.dump_memdb())
+
+    lines = index.get_source_contents(0).decode('utf-8').splitlines()
+    for view in index, v1_index, mem_index:
+        for idx in 0, 20, len(lines) - 1:
+            assert view.get_source_line(0, idx) == lines[idx]
+        assert view.get_source_context(0, 20, 3, 2) == \
+            (lines[17:20], lines[20], lines[21:23])
+
+
+def test_lookup_original():
+    source, min_map = get_fixtures('react-dom')
+    index = View.from_json(min_map)
+    views = [
+        index,
+        View.from_memdb(index.dump_memdb()),
+        View.from_memdb(index.dump_memdb(with_reverse_index=False)),
+    ]
+
+    by_original = {}
+    for token in index:
+        if token.src is not None:
+            by_original.setdefault(
+                (token.src_id, token.src_line, token.src_col), []).append(
+                    (token.dst_line, token.dst_col))
+
+    for (src_id, line, col), expected in sorted(by_original.items())[::97]:
+        for view in views:
+            tokens = view.lookup_original(src_id, line, col)
+            assert sorted(expected) == \
+                [(t.dst_line, t.dst_col) for t in tokens]
+            for token in tokens:
+                assert (token.src_id, token.src_line, token.src_col) == \
+                    (src_id, line, col)
+
+    # columns between tokens go back to the closest column before them and
+    # columns before the first token move forward.
+    react_token = index.lookup_token(0, 319)
+    for view in views:
+        tokens = view.lookup_original(0, react_token.src_line,
+                                      react_token.src_col + 1)
+        assert (0, 319) in [(t.dst_line, t.dst_col) for t in tokens]
+        tokens = view.lookup_original(0, react_token.src_line)
+        first_col = min(col for (src_id, line, col) in by_original
+                        if (src_id, line) == (0, react_token.src_line))
+        assert tokens and all(t.src_col == first_col for t in tokens)
+        assert view.lookup_original(0, 1000000, 0) == []
+        assert view.lookup_original(42, 0, 0) == []
+
+
+def test_token_spans():
+    source, min_map = get_fixtures('react-dom')
+    index = View.from_json(min_map)
+    mem_index = View.from_memdb(index.dump_memdb())
+
+    for view in index, mem_index:
+        tokens = list(view)
+        for token, next_token in zip(tokens, tokens[1:]):
+            assert token.exact
+            if next_token.dst_line == token.dst_line:
+                assert token.dst_end_col == next_token.dst_col
+            else:
+                assert token.dst_end_col is None
+        assert tokens[-1].dst_end_col is None
+
+        token = view.lookup_token(0, 319)
+        assert token.exact
+        next_col = token.dst_end_col
+        assert next_col > 320
+        token = view.lookup_token(0, 320)
+        assert not token.exact
+        assert (token.dst_col, token.dst_end_col) == (319, next_col)
+        assert view.lookup_token(0, next_col).dst_col == next_col
+
+
+def test_lookup_options():
+    index = View.from_json(
+        b'{"version":3,"sources":["foo.js"],"names":[],'
+        b'"mappings":"AAAA,UAAU;;UAEV"}')
+    mem_index = View.from_memdb(index.dump_memdb())
+
+    for view in index, mem_index:
+        assert view.lookup_token(0, 15).dst_col == 10
+        assert view.lookup_token(0, 15, bias='lub').dst_line == 2
+        assert view.lookup_token(0, 15, bias='lub', same_line=True) is None
+        assert view.lookup_token(0, 5, bias='lub').dst_col == 10
+        assert not view.lookup_token(0, 5, bias='lub').exact
+        assert view.lookup_token(0, 10, bias='lub').exact
+
+        # the start of line 2 falls back to the previous mapped line
+        assert view.lookup_token(2, 3).dst_line == 0
+        assert view.lookup_token(2, 3, same_line=True) is None
+        assert view.lookup_token(2, 3, bias='lub').dst_col == 10
+        assert view.lookup_token(1, 0, bias='lub').dst_line == 2
+        assert view.lookup_token(1, 0, bias='lub', same_line=True) is None
+
+        assert view.lookup_token(0, 15, max_distance=5).dst_col == 10
+        assert view.lookup_token(0, 16, max_distance=5) is None
+        assert view.lookup_token(2, 7, bias='lub', max_distance=3).dst_col == 10
+        assert view.lookup_token(2, 3, max_distance=100) is None
+
+        with pytest.raises(ValueError):
+            view.lookup_token(0, 0, bias='nearest')
+
+
+def test_compose():
+    source, min_map = get_fixtures('jquery')
+    view = View.from_json(min_map)
+    src = view.get_source_name(0)
+
+    # composing with a map that maps the source to itself changes nothing
+    # but the source contents come from the inner map
+    identity = View.from_json(make_sourcemap(
+        [(tok.src_line, tok.src_col, src, tok.src_line, tok.src_col, None)
+         for tok in view],
+        sources_content={src: source.decode('utf-8')}))
+    composed = view.compose(identity)
+    assert list(composed) == list(view)
+    assert composed.get_source_contents(0) == source
+    verify_token_search(composed)
+
+
+def test_compose_chain():
+    # original.ts -> intermediate.js -> minified.js
+    inner = View.from_json(make_sourcemap([
+        (1, 0, 'original.ts', 0, 0, None),
+        (1, 9, 'original.ts', 0, 9, 'add'),
+        (2, 2, 'original.ts', 1, 2, None),
+        (2, 9, 'original.ts', 1, 9, 'left'),
+    ], sources_content={'original.ts': u'function add(left, right) {}'}))
+    outer = View.from_json(make_sourcemap([
+        (0, 0, 'intermediate.js', 0, 0, None),
+        (0, 13, 'intermediate.js', 1, 0, None),
+        (0, 22, 'intermediate.js', 1, 9, 'add'),
+        (0, 30, 'intermediate.js', 2, 2, None),
+        (0, 37, 'intermediate.js', 2, 9, 'n'),
+        (0, 40, 'intermediate.js', 2, 13, 'r'),
+    ]))
+
+    composed = outer.compose(inner)
+    assert [tuple(tok) for tok in composed] == [
+        (0, 0, None, 0, 0, 0xffffffff, None),
+        (0, 13, 'original.ts', 0, 0, 0, None),
+        (0, 22, 'original.ts', 0, 9, 0, 'add'),
+        (0, 30, 'original.ts', 1, 2, 0, None),
+        # names come from the deepest map
+        (0, 37, 'original.ts', 1, 9, 0, 'left'),
+        # columns between inner tokens resolve to the one before
+        (0, 40, 'original.ts', 1, 9, 0, 'left'),
+    ]
+    assert composed.get_source_contents(0) == \
+        b'function add(left, right) {}'
+    # the first line of the intermediate file has no mapping
+    assert composed.lookup_token(0, 5).src is None
+
+
+def test_compose_gaps():
+    inner = View.from_json(make_sourcemap([
+        (0, 0, 'orig.ts', 3, 0, None),
+        (2, 0, 'orig.ts', 7, 0, None),
+    ]))
+    outer = json.loads(make_sourcemap([
+        (0, 0, 'intermediate.js', 2, 0, None),
+        # the first line of the intermediate file has no mapping
+        (0, 12, 'intermediate.js', 1, 4, None),
+        (0, 20, 'intermediate.js', 0, 0, None),
+        (0, 25, None, 0, 0, None),
+        (0, 30, 'intermediate.js', 2, 0, None),
+    ]))
+    outer['file'] = 'minified.js'
+    outer = View.from_json(json.dumps(outer))
+
+    composed = outer.compose(inner)
+    assert [tuple(tok)[:5] for tok in composed] == [
+        (0, 0, 'orig.ts', 7, 0),
+        (0, 12, None, 0, 0),
+        (0, 20, 'orig.ts', 3, 0),
+        (0, 25, None, 0, 0),
+        (0, 30, 'orig.ts', 7, 0),
+    ]
+    # gaps do not inherit the previous mapping
+    assert composed.lookup_token(0, 14).src is None
+    assert composed.lookup_token(0, 27).src is None
+    assert composed.lookup_token(0, 22).src_line == 3
+    assert json.loads(composed.dump_json())['file'] == 'minified.js'
+
+
+def test_compose_unresolved():
+    inner = View.from_json(make_sourcemap([(0, 4, 'orig.ts', 1, 0, None)]))
+    outer = View.from_json(make_sourcemap([
+        # before the first token of the intermediate file
+        (0, 0, 'intermediate.js', 0, 0, 'a'),
+        (0, 10, 'intermediate.js', 0, 6, None),
+        # past the end of the intermediate file
+        (1, 0, 'intermediate.js', 9, 0, None),
+    ]))
+    for other in inner, View.from_memdb(inner.dump_memdb()):
+        composed = outer.compose(other)
+        # unresolved tokens survive as unmapped tokens
+        assert len(composed) == len(outer)
+        assert [tuple(tok)[:5] for tok in composed] == [
+            (0, 0, None, 0, 0),
+            (0, 10, 'orig.ts', 1, 0),
+            (1, 0, None, 0, 0),
+        ]
+        assert composed.lookup_token(0, 5).src is None
+        assert composed.lookup_token(1, 5).src is None
+
+
+def test_builder():
+    builder = Builder(file='minified.js',
+                      debug_id='85314830-023f-4cf1-a267-535f4e37bb17')
+    src = builder.add_source('original.js')
+    assert builder.add_source('other.js') == src + 1
+    # sources and names are deduplicated
+    assert builder.add_source('original.js') == src
+    name = builder.add_name('add')
+    assert builder.add_name('add') == name
+    builder.set_source_contents(src, u'function add(a, b) {}')
+
+    # tokens do not need to be added in order
+    builder.add_token(0, 15, 0, 13, src)
+    builder.add_token(0, 0, 0, 0, src)
+    builder.add_token(0, 9, 0, 9, src, name)
+    view = builder.into_view()
+
+    assert [tuple(tok) for tok in view] == [
+        (0, 0, 'original.js', 0, 0, 0, None),
+        (0, 9, 'original.js', 0, 9, 0, 'add'),
+        (0, 15, 'original.js', 0, 13, 0, None),
+    ]
+    assert view.get_source_contents(src) == b'function add(a, b) {}'
+    assert view.get_source_contents(1) is None
+    assert view.get_debug_id() == '85314830-023f-4cf1-a267-535f4e37bb17'
+    verify_token_search(view)
+
+    for other in [View.from_json(view.dump_json()),
+                  View.from_memdb(view.dump_memdb())]:
+        assert list(other) == list(view)
+        assert other.get_source_contents(src) == b'function add(a, b) {}'
+        assert other.get_debug_id() == view.get_debug_id()
+
+    with pytest.raises(RuntimeError):
+        builder.add_source('late.js')
+
+
+def test_builder_unknown_ids():
+    builder = Builder()
+    src = builder.add_source('original.js')
+    with pytest.raises(UnknownId):
+        builder.add_token(0, 0, 0, 0, src + 1)
+    with pytest.raises(UnknownId):
+        builder.add_token(0, 0, 0, 0, src, 0)
+    with pytest.raises(UnknownId):
+        builder.set_source_contents(src + 1, u'')
+    builder.add_token(0, 0, 0, 0, src)
+    view = builder.into_view()
+    assert len(view) == 1
+    assert view.get_debug_id() is None
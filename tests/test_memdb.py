@@ -0,0 +1,375 @@
+import gc
+import io
+import json
+import mmap
+import random
+import struct
+import tempfile
+import threading
+
+import pytest
+
+from libsourcemap import View, from_json, BadMemDb, UnsupportedMemDbVersion, \
+    UnsupportedMemDbByteOrder, MemDbChecksumMismatch, BadCompressionLevel
+from libsourcemap.highlevel import rustcall, _lib, _ffi
+
+from testutils import get_fixtures, verify_index, verify_token_equivalence
+
+
+def exercise_view(view, strict=True):
+    try:
+        for token in view:
+            view.lookup_token(token.dst_line, token.dst_col)
+    except IndexError:
+        # unvalidated views can fail to read tokens
+        if strict:
+            raise
+    for src_id, _ in view.iter_sources():
+        view.get_source_contents(src_id)
+
+
+def test_validate():
+    source, min_map = get_fixtures('react-dom-full')
+    memdb = View.from_json(min_map).dump_memdb()
+    view = View.from_memdb(memdb, validate=True)
+    verify_index(view, source)
+
+    with open('tests/fixtures/react-dom-full.min.v1.memdb', 'rb') as f:
+        View.from_memdb(f.read(), validate=True)
+
+
+def test_truncated():
+    _, min_map = get_fixtures('react-dom-full')
+    memdb = View.from_json(min_map).dump_memdb()
+
+    for length in range(len(memdb)):
+        truncated = memdb[:length]
+        with pytest.raises(BadMemDb):
+            View.from_memdb(truncated, validate=True)
+
+        # without validation loading might fail but accessors must not
+        # panic.
+        try:
+            view = View.from_memdb(truncated)
+        except BadMemDb:
+            continue
+        exercise_view(view, strict=False)
+
+
+def test_corrupted():
+    _, min_map = get_fixtures('react-dom-full')
+    memdb = bytearray(View.from_json(min_map).dump_memdb())
+
+    rng = random.Random(42)
+    for _ in range(500):
+        corrupted = memdb[:]
+        for _ in range(rng.randint(1, 4)):
+            corrupted[rng.randrange(len(corrupted))] = rng.randrange(256)
+        corrupted = bytes(corrupted)
+
+        try:
+            view = View.from_memdb(corrupted, validate=True)
+        except (BadMemDb, UnsupportedMemDbVersion):
+            pass
+        else:
+            exercise_view(view)
+
+        try:
+            view = View.from_memdb(corrupted)
+        except (BadMemDb, UnsupportedMemDbVersion):
+            continue
+        exercise_view(view, strict=False)
+
+
+def test_checksum_mismatch():
+    _, min_map = get_fixtures('react-dom-full')
+    memdb = bytearray(View.from_json(min_map).dump_memdb())
+
+    # flip a bit in the middle of the file which does not break the
+    # structure but changes the contents.
+    for offset in range(0, len(memdb), 97):
+        corrupted = memdb[:]
+        corrupted[offset] ^= 0x10
+        corrupted = bytes(corrupted)
+        with pytest.raises((MemDbChecksumMismatch,
+                            UnsupportedMemDbVersion)) as excinfo:
+            View.from_memdb(corrupted, validate=True)
+        if excinfo.type is UnsupportedMemDbVersion:
+            assert offset < 4
+
+
+def test_byte_order():
+    _, min_map = get_fixtures('react-dom-full')
+    memdb = View.from_json(min_map).dump_memdb()
+
+    # the format is little-endian independent of the host
+    version, = struct.unpack('<I', memdb[:4])
+    assert version == 2
+
+    # a file written by a big-endian host has its version swapped
+    swapped = struct.pack('>I', version) + memdb[4:]
+    with pytest.raises(UnsupportedMemDbByteOrder):
+        View.from_memdb(swapped)
+
+
+def test_dump_to_stream():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+    memdb = view.dump_memdb(minified_source=source)
+
+    f = io.BytesIO()
+    view.dump_memdb_to_stream(f, minified_source=source)
+    assert f.getvalue() == memdb
+
+    f = io.BytesIO()
+    view.dump_memdb_to_stream(f, with_source_contents=False, with_names=False)
+    assert f.getvalue() == view.dump_memdb(with_source_contents=False,
+                                           with_names=False)
+
+    mem_view = View.from_memdb(memdb)
+    f = io.BytesIO()
+    mem_view.dump_memdb_to_stream(f, with_names=False)
+    assert f.getvalue() == view.dump_memdb(with_names=False)
+
+
+def test_dump_to_stream_error():
+    _, min_map = get_fixtures('react-dom-full')
+
+    class BrokenStream(object):
+        def write(self, data):
+            raise ValueError('broken')
+
+    with pytest.raises(ValueError) as excinfo:
+        View.from_json(min_map).dump_memdb_to_stream(BrokenStream())
+    assert str(excinfo.value) == 'broken'
+
+
+def test_dump_to_path():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+
+    with tempfile.NamedTemporaryFile() as f:
+        view.dump_memdb_to_path(f.name, minified_source=source)
+        with open(f.name, 'rb') as f2:
+            assert f2.read() == view.dump_memdb(minified_source=source)
+        View.from_memdb_file(f.name, validate=True)
+
+
+def test_dump_legacy_abi():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+
+    # the original entry point keeps its arguments and free function
+    len_out = _ffi.new('unsigned int *')
+    buf = rustcall(_lib.lsm_view_dump_memdb, view._get_ptr(), len_out, 1, 0)
+    try:
+        memdb = _ffi.unpack(buf, len_out[0])
+    finally:
+        _lib.lsm_buffer_free(buf)
+    assert memdb == view.dump_memdb(with_names=False)
+
+
+def test_transcoding():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+    mem_view = View.from_memdb(view.dump_memdb(minified_source=source))
+
+    # function names are carried over from the original memdb
+    assert mem_view.dump_memdb() == view.dump_memdb(minified_source=source)
+    for kwargs in [dict(with_source_contents=False), dict(with_names=False),
+                   dict(with_source_contents=False, with_names=False)]:
+        assert mem_view.dump_memdb(**kwargs) == \
+            view.dump_memdb(minified_source=source, **kwargs)
+
+    slim_view = View.from_memdb(mem_view.dump_memdb(
+        with_source_contents=False, with_names=False))
+    assert slim_view.get_source_contents(0) is None
+    # stripped data cannot come back
+    assert slim_view.dump_memdb() == view.dump_memdb(
+        with_source_contents=False, with_names=False)
+
+
+def test_compression():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+    contents = view.get_source_contents(0)
+    sizes = {}
+
+    for compression in 'brotli', 'zstd', 'deflate', None:
+        memdb = view.dump_memdb(compression=compression)
+        sizes[compression] = len(memdb)
+        mem_view = View.from_memdb(memdb, validate=True)
+        assert mem_view.get_source_contents(0) == contents
+        verify_token_equivalence(view, mem_view)
+
+        # the copying entry point hands out buffers for lsm_buffer_free
+        len_out = _ffi.new('unsigned int *')
+        must_free = _ffi.new('int *')
+        buf = rustcall(_lib.lsm_view_get_source_contents,
+                       mem_view._get_ptr(), 0, len_out, must_free)
+        assert _ffi.unpack(buf, len_out[0]) == contents
+        if must_free[0]:
+            _lib.lsm_buffer_free(buf)
+
+        # re-dumping keeps the codec unless a different one is requested
+        assert mem_view.dump_memdb(compression=compression) == memdb
+        assert mem_view.dump_memdb(compression='zstd') == \
+            view.dump_memdb(compression='zstd')
+
+    assert sizes[None] > max(sizes['brotli'], sizes['zstd'], sizes['deflate'])
+    assert len(view.dump_memdb(compression='brotli', compression_level=11)) \
+        < sizes['brotli']
+
+    with pytest.raises(ValueError):
+        view.dump_memdb(compression='lzma')
+    for compression, level in [('brotli', 12), ('zstd', 0), ('zstd', 23),
+                               ('deflate', 10), ('deflate', 100)]:
+        with pytest.raises(ValueError):
+            view.dump_memdb(compression=compression, compression_level=level)
+
+    # the C ABI rejects them as well instead of panicking
+    for codec, level in [(0, 100), (2, 1000), (3, 100), (3, -1)]:
+        with pytest.raises(BadCompressionLevel):
+            rustcall(_lib.lsm_view_dump_memdb_with_options, view._get_ptr(),
+                     _ffi.new('unsigned int *'), 1, 1, 1, _ffi.NULL,
+                     codec, level)
+
+
+def test_source_cache():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+    contents = view.get_source_contents(0)
+    mem_view = View.from_memdb(view.dump_memdb())
+
+    for size in 0, 1024, len(contents), 32 * 1024 * 1024:
+        mem_view.set_source_cache_size(size)
+        for _ in range(3):
+            assert mem_view.get_source_contents(0) == contents
+            assert mem_view.get_source_contents(1) is None
+
+    def lookup():
+        for _ in range(20):
+            assert mem_view.get_source_contents(0) == contents
+
+    threads = [threading.Thread(target=lookup) for _ in range(4)]
+    for thread in threads:
+        thread.start()
+    # shrinking the cache while it is used must not invalidate results
+    for size in 0, len(contents), 0:
+        mem_view.set_source_cache_size(size)
+    for thread in threads:
+        thread.join()
+
+    # json views do not have a cache
+    view.set_source_cache_size(0)
+    assert view.get_source_contents(0) == contents
+
+
+def test_debug_id():
+    debug_id = '85314830-023f-4cf1-a267-535f4e37bb17'
+    source, min_map = get_fixtures('react-dom-full')
+    assert View.from_json(min_map).get_debug_id() is None
+
+    min_map_with_id = min_map.replace(
+        b'{', b'{"debugId":"%s",' % debug_id.encode('ascii'), 1)
+    legacy_min_map = min_map.replace(
+        b'{', b'{"debug_id":"%s",' % debug_id.encode('ascii'), 1)
+    for view in [View.from_json(min_map_with_id), from_json(min_map_with_id),
+                 View.from_json(legacy_min_map)]:
+        assert view.get_debug_id() == debug_id
+        verify_token_equivalence(view, View.from_json(min_map))
+
+        mem_view = View.from_memdb(view.dump_memdb(), validate=True)
+        assert mem_view.get_debug_id() == debug_id
+        assert View.from_memdb(mem_view.dump_memdb(
+            with_source_contents=False)).get_debug_id() == debug_id
+
+        stream = io.BytesIO()
+        view.dump_memdb_to_stream(stream)
+        assert stream.getvalue() == view.dump_memdb()
+
+    mem_view = View.from_memdb(View.from_json(min_map).dump_memdb())
+    assert mem_view.get_debug_id() is None
+    assert b'debugId' not in mem_view.dump_json()
+    with open('tests/fixtures/react-dom-full.min.v1.memdb', 'rb') as f:
+        assert View.from_memdb(f.read()).get_debug_id() is None
+
+
+def test_debug_id_json():
+    debug_id = '85314830-023f-4cf1-a267-535f4e37bb17'
+    _, min_map = get_fixtures('coolstuff')
+    view = View.from_json(min_map.replace(
+        b'{', b'{"debugId":"%s",' % debug_id.encode('ascii'), 1))
+    for other in [view, View.from_memdb(view.dump_memdb())]:
+        dumped = other.dump_json()
+        assert json.loads(dumped.decode('utf-8'))['debugId'] == debug_id
+        assert View.from_json(dumped).get_debug_id() == debug_id
+        verify_token_equivalence(View.from_json(dumped), view)
+
+    # escaped and non-BMP debug ids survive a roundtrip
+    view = View.from_json(min_map.replace(
+        b'{', b'{"debugId":"a\\"b\\\\\\ud83d\\ude00\\u00e9",', 1))
+    escaped_id = u'a"b\\\U0001f600\xe9'
+    assert view.get_debug_id() == escaped_id
+    for other in [view, View.from_memdb(view.dump_memdb())]:
+        dumped = other.dump_json()
+        assert json.loads(dumped.decode('utf-8'))['debugId'] == escaped_id
+        assert View.from_json(dumped).get_debug_id() == escaped_id
+
+    # only the top-level keys are considered
+    for data, expected in [
+        (b'{"sourcesContent":["{\\"debugId\\":\\"nope\\"}"],'
+         b'"x":{"debugId":"nope"},"debugId":"a\\"b\\u0063"', 'a"bc'),
+        (b')]}\'\n{"debug_id":"legacy","x":["debugId"]', 'legacy'),
+        (b'{"debug_id":"legacy","debugId":"new"', 'new'),
+        (b'{"debugId":null,"x":"debugId"', None),
+        (b'{"debugId":""', None),
+        (b'{"debugId":"\\ud83d\\ude00x"', u'\U0001f600x'),
+    ]:
+        min_map = data + b',"version":3,"sources":[],"names":[],"mappings":""}'
+        assert View.from_json(min_map).get_debug_id() == expected, data
+
+
+def test_upgrade_v1():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+    with open('tests/fixtures/react-dom-full.min.v1.memdb', 'rb') as f:
+        v1_view = View.from_memdb(f.read())
+
+    memdb = v1_view.dump_memdb()
+    assert memdb == view.dump_memdb()
+    upgraded = View.from_memdb(memdb, validate=True)
+    verify_index(upgraded, source)
+    verify_token_equivalence(view, upgraded)
+
+
+def test_borrowed():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+    memdb = view.dump_memdb()
+
+    for buffer in [memdb, bytearray(memdb), memoryview(memdb)]:
+        mem_view = View.from_memdb(buffer, copy=False, validate=True)
+        # the view has to keep the buffer alive
+        del buffer
+        gc.collect()
+        verify_index(mem_view, source)
+        verify_token_equivalence(view, mem_view)
+
+    with pytest.raises(BadMemDb):
+        View.from_memdb(memdb[:100], copy=False, validate=True)
+
+
+def test_borrowed_mmap():
+    source, min_map = get_fixtures('react-dom-full')
+    view = View.from_json(min_map)
+
+    with tempfile.NamedTemporaryFile() as f:
+        f.write(view.dump_memdb())
+        f.flush()
+        m = mmap.mmap(f.fileno(), 0, access=mmap.ACCESS_READ)
+        mem_view = View.from_memdb(m, copy=False)
+        verify_index(mem_view, source)
+        del mem_view
+        gc.collect()
+        m.close()
@@ -1,3 +1,5 @@
+import json
+
 try:
     from itertools import izip
 except ImportError:
@@ -68,3 +70,62 @@ def verify_token_search(index):
         for col in rng:
             token_match = index.lookup_token(token.dst_line, col)
             assert token_match == token
+
+
+_b64_chars = 'ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/'
+
+
+def encode_vlq(value):
+    value = ((-value) << 1) | 1 if value < 0 else value << 1
+    rv = []
+    while True:
+        digit = value & 31
+        value >>= 5
+        if value:
+            digit |= 32
+        rv.append(_b64_chars[digit])
+        if not value:
+            return ''.join(rv)
+
+
+def make_sourcemap(tokens, sources_content=None):
+    """Builds a JSON sourcemap from ``(dst_line, dst_col, src, src_line,
+    src_col, name)`` tuples.  Tokens without a `src` are unmapped.
+    `sources_content` maps sources to contents.
+    """
+    sources = []
+    names = []
+    lines = []
+    prev_col = prev_src = prev_line = prev_src_col = prev_name = 0
+    for dst_line, dst_col, src, src_line, src_col, name in sorted(
+            tokens, key=lambda x: (x[0], x[1])):
+        while len(lines) <= dst_line:
+            lines.append([])
+            prev_col = 0
+        if src is None:
+            lines[dst_line].append(encode_vlq(dst_col - prev_col))
+            prev_col = dst_col
+            continue
+        if src not in sources:
+            sources.append(src)
+        src_id = sources.index(src)
+        segment = [dst_col - prev_col, src_id - prev_src,
+                   src_line - prev_line, src_col - prev_src_col]
+        prev_col, prev_src, prev_line, prev_src_col = \
+            dst_col, src_id, src_line, src_col
+        if name is not None:
+            if name not in names:
+                names.append(name)
+            segment.append(names.index(name) - prev_name)
+            prev_name = names.index(name)
+        lines[dst_line].append(''.join(encode_vlq(x) for x in segment))
+
+    rv = {
+        'version': 3,
+        'sources': sources,
+        'names': names,
+        'mappings': ';'.join(','.join(line) for line in lines),
+    }
+    if sources_content is not None:
+        rv['sourcesContent'] = [sources_content.get(x) for x in sources]
+    return json.dumps(rv)